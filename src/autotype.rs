@@ -0,0 +1,270 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use anyhow::{anyhow, Result};
+
+use passage::config::{AutotypeBackend, AutotypeConfig};
+use passage::Entry;
+
+/// Keys which can be pressed as part of an autotype sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Tab,
+    Enter,
+    Space,
+}
+
+/// A single step of an autotype sequence
+#[derive(Debug, PartialEq, Eq)]
+pub enum Action {
+    Type(String),
+    Press(Key),
+}
+
+/// Something which can send keystrokes to the focused window
+pub trait Typist {
+    fn type_text(&mut self, text: &str) -> Result<()>;
+    fn press(&mut self, key: Key) -> Result<()>;
+}
+
+/// Returns the sequence to use for `entry`, unless one is given explicitly
+pub fn default_sequence(config: &AutotypeConfig, entry: &Entry) -> String {
+    entry
+        .autotype
+        .as_ref()
+        .or(config.sequence.as_ref())
+        .map_or_else(
+            || {
+                if entry.username.is_some() {
+                    "{username}{TAB}{password}{ENTER}".to_string()
+                } else {
+                    "{password}{ENTER}".to_string()
+                }
+            },
+            String::clone,
+        )
+}
+
+/// Turns a sequence like `{username}{TAB}{password}{ENTER}` into actions, filling in
+/// the fields of `entry`
+pub fn parse(sequence: &str, entry: &Entry) -> Result<Vec<Action>> {
+    let mut actions = vec![];
+    let mut text = String::new();
+    let mut rest = sequence;
+
+    while let Some(start) = rest.find('{') {
+        text.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| anyhow!("unclosed {{ in autotype sequence: {}", sequence))?;
+        let placeholder = &rest[start + 1..start + end];
+        match placeholder {
+            "username" => text.push_str(
+                entry
+                    .username
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("entry has no username"))?,
+            ),
+            "password" => text.push_str(&entry.password),
+            _ => {
+                let key = match placeholder {
+                    "TAB" => Key::Tab,
+                    "ENTER" => Key::Enter,
+                    "SPACE" => Key::Space,
+                    _ => return Err(anyhow!("unknown autotype placeholder: {{{}}}", placeholder)),
+                };
+                if !text.is_empty() {
+                    actions.push(Action::Type(std::mem::take(&mut text)));
+                }
+                actions.push(Action::Press(key));
+            }
+        }
+        rest = &rest[start + end + 1..];
+    }
+    text.push_str(rest);
+    if !text.is_empty() {
+        actions.push(Action::Type(text));
+    }
+
+    Ok(actions)
+}
+
+/// Types into the focused window using `xdotool` (X11), `ydotool` or `wtype` (Wayland)
+#[derive(Debug, Clone, Copy)]
+enum Tool {
+    Xdotool,
+    Ydotool,
+    Wtype,
+}
+
+impl Tool {
+    fn run(self, args: &[&str], input: Option<&str>) -> Result<()> {
+        let program = match self {
+            Self::Xdotool => "xdotool",
+            Self::Ydotool => "ydotool",
+            Self::Wtype => "wtype",
+        };
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| anyhow!("failed to run {}: {}", program, e))?;
+        if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+            stdin.write_all(input.as_bytes())?;
+        }
+        if child.wait()?.success() {
+            Ok(())
+        } else {
+            Err(anyhow!("{} failed", program))
+        }
+    }
+}
+
+impl Typist for Tool {
+    fn type_text(&mut self, text: &str) -> Result<()> {
+        // the text is passed on stdin, so it doesn't show up in the process list
+        match self {
+            Self::Xdotool => self.run(&["type", "--clearmodifiers", "--file", "-"], Some(text)),
+            Self::Ydotool => self.run(&["type", "--file", "-"], Some(text)),
+            Self::Wtype => self.run(&["-"], Some(text)),
+        }
+    }
+
+    fn press(&mut self, key: Key) -> Result<()> {
+        let name = match key {
+            Key::Tab => "Tab",
+            Key::Enter => "Return",
+            Key::Space => "space",
+        };
+        match self {
+            Self::Xdotool => self.run(&["key", "--clearmodifiers", name], None),
+            Self::Ydotool => {
+                // ydotool takes Linux input event codes, pressed (1) and released (0)
+                let codes = match key {
+                    Key::Tab => ["15:1", "15:0"],
+                    Key::Enter => ["28:1", "28:0"],
+                    Key::Space => ["57:1", "57:0"],
+                };
+                self.run(&["key", codes[0], codes[1]], None)
+            }
+            Self::Wtype => self.run(&["-k", name], None),
+        }
+    }
+}
+
+/// Picks the tool matching the current session, unless one is configured
+fn open(config: &AutotypeConfig) -> Result<Tool> {
+    match config.backend {
+        AutotypeBackend::Auto => {
+            if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+                Ok(Tool::Wtype)
+            } else if std::env::var_os("DISPLAY").is_some() {
+                Ok(Tool::Xdotool)
+            } else {
+                Err(anyhow!(
+                    "can't autotype (neither WAYLAND_DISPLAY nor DISPLAY is set)"
+                ))
+            }
+        }
+        AutotypeBackend::Xdotool => Ok(Tool::Xdotool),
+        AutotypeBackend::Ydotool => Ok(Tool::Ydotool),
+        AutotypeBackend::Wtype => Ok(Tool::Wtype),
+    }
+}
+
+fn perform(typist: &mut dyn Typist, actions: &[Action]) -> Result<()> {
+    for action in actions {
+        match action {
+            Action::Type(text) => typist.type_text(text)?,
+            Action::Press(key) => typist.press(*key)?,
+        }
+    }
+    Ok(())
+}
+
+/// Types `sequence` for `entry` into the focused window
+pub fn autotype(config: &AutotypeConfig, entry: &Entry, sequence: &str) -> Result<()> {
+    let actions = parse(sequence, entry)?;
+    perform(&mut open(config)?, &actions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(username: Option<&str>) -> Entry {
+        Entry {
+            password: "hunter2".to_string(),
+            username: username.map(str::to_string),
+            autotype: None,
+        }
+    }
+
+    #[derive(Default)]
+    struct Fake(Vec<String>);
+
+    impl Typist for Fake {
+        fn type_text(&mut self, text: &str) -> Result<()> {
+            self.0.push(text.to_string());
+            Ok(())
+        }
+
+        fn press(&mut self, key: Key) -> Result<()> {
+            self.0.push(format!("{:?}", key));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let actions = parse("{username}{TAB}{password}{ENTER}", &entry(Some("bob"))).unwrap();
+        assert_eq!(
+            actions,
+            vec![
+                Action::Type("bob".to_string()),
+                Action::Press(Key::Tab),
+                Action::Type("hunter2".to_string()),
+                Action::Press(Key::Enter),
+            ]
+        );
+
+        let actions = parse("user:{SPACE}{password}!", &entry(None)).unwrap();
+        assert_eq!(
+            actions,
+            vec![
+                Action::Type("user:".to_string()),
+                Action::Press(Key::Space),
+                Action::Type("hunter2!".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse("{username}", &entry(None)).is_err());
+        assert!(parse("{password", &entry(None)).is_err());
+        assert!(parse("{otp}", &entry(None)).is_err());
+    }
+
+    #[test]
+    fn test_default_sequence() {
+        let config = AutotypeConfig::default();
+        assert_eq!(default_sequence(&config, &entry(None)), "{password}{ENTER}");
+
+        let mut with_sequence = entry(Some("bob"));
+        assert_eq!(
+            default_sequence(&config, &with_sequence),
+            "{username}{TAB}{password}{ENTER}"
+        );
+        with_sequence.autotype = Some("{password}".to_string());
+        assert_eq!(default_sequence(&config, &with_sequence), "{password}");
+    }
+
+    #[test]
+    fn test_perform() {
+        let mut fake = Fake::default();
+        let actions = parse("{username}{TAB}{password}{ENTER}", &entry(Some("bob"))).unwrap();
+        perform(&mut fake, &actions).unwrap();
+        assert_eq!(fake.0, vec!["bob", "Tab", "hunter2", "Enter"]);
+    }
+}
@@ -0,0 +1,369 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::Debug;
+use std::fs::{self, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::config::{BackendKind, Config};
+use crate::{entries_file, Error, Result};
+
+mod s3;
+mod webdav;
+
+pub use s3::{Credentials, S3Backend};
+pub use webdav::{BasicAuth, WebDavBackend};
+
+/// How long `FileBackend::lock` waits for another process to release the lock
+const LOCK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Locks older than this were left behind by a crashed process, writing the storage takes
+/// a fraction of a second
+const STALE_LOCK_AGE: Duration = Duration::from_secs(30);
+
+/// Identifies one state of the stored data, writes only succeed if the data is still at
+/// the version they expect. What it contains is up to the backend.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version(pub String);
+
+/// Held while a backend is locked, dropping it releases the lock
+pub struct Lock {
+    release: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl Lock {
+    pub fn new(release: impl FnOnce() + Send + 'static) -> Self {
+        Self {
+            release: Some(Box::new(release)),
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        if let Some(release) = self.release.take() {
+            release();
+        }
+    }
+}
+
+/// Where the encrypted storage lives. Backends only move opaque bytes around, they never
+/// see any secrets.
+pub trait StorageBackend: Debug + Send {
+    /// Returns the encrypted storage and its version, `None` if there is no storage yet
+    fn read(&self) -> Result<Option<(Vec<u8>, Version)>>;
+
+    /// Replaces the encrypted storage if it's still at `expected` (`None` meaning it must
+    /// not exist yet) and returns the new version, fails with `Error::Conflict` otherwise
+    fn write(&self, data: &[u8], expected: Option<&Version>) -> Result<Version>;
+
+    /// Keeps other writers out until the returned `Lock` is dropped
+    fn lock(&self) -> Result<Lock>;
+
+    /// Returns whether there is a storage yet
+    fn exists(&self) -> Result<bool> {
+        Ok(self.read()?.is_some())
+    }
+}
+
+/// Returns the backend the storage of `config` lives in
+pub fn open(config: &Config) -> Result<Box<dyn StorageBackend>> {
+    Ok(match config.storage_backend() {
+        BackendKind::File => Box::new(FileBackend::new(entries_file(config)?)),
+        BackendKind::S3 => Box::new(S3Backend::new(
+            config.s3_config(),
+            Credentials::from_env()?,
+        )?),
+        BackendKind::WebDav => {
+            let webdav = config.webdav_config();
+            Box::new(WebDavBackend::new(webdav, BasicAuth::from_config(webdav)?)?)
+        }
+    })
+}
+
+fn checksum(data: &[u8]) -> Version {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    Version(format!("{:016x}", hasher.finish()))
+}
+
+/// Returns whether the lock file at `path` was left behind, because the process holding it
+/// is gone or got it too long ago to still be writing. Lock files hold the process id and
+/// the time they were taken at.
+fn is_stale(path: &Path) -> bool {
+    let Ok(contents) = fs::read_to_string(path) else {
+        return false;
+    };
+    let mut parts = contents.split_whitespace();
+    let pid = parts.next().and_then(|pid| pid.parse().ok());
+    let since = parts
+        .next()
+        .and_then(|secs| secs.parse().ok())
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+        // a lock file which is still being written (or was left by an older version)
+        .or_else(|| fs::metadata(path).and_then(|m| m.modified()).ok());
+    let too_old = since
+        .and_then(|since| since.elapsed().ok())
+        .is_some_and(|age| age >= STALE_LOCK_AGE);
+    too_old || pid.is_some_and(|pid| !is_running(pid))
+}
+
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    use nix::errno::Errno;
+    use nix::sys::signal::kill;
+    use nix::unistd::Pid;
+    use std::convert::TryFrom;
+
+    // signal 0 only checks whether the process exists
+    i32::try_from(pid).map_or(true, |pid| {
+        !matches!(kill(Pid::from_raw(pid), None), Err(Errno::ESRCH))
+    })
+}
+
+#[cfg(not(unix))]
+const fn is_running(_pid: u32) -> bool {
+    true
+}
+
+/// Keeps the storage in a local file, which is replaced atomically on every write. The
+/// version is a checksum of the contents.
+#[derive(Debug)]
+pub struct FileBackend {
+    path: PathBuf,
+}
+
+impl FileBackend {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    fn sibling(&self, extension: &str) -> PathBuf {
+        let mut name = self.path.file_name().unwrap_or_default().to_os_string();
+        name.push(extension);
+        self.path.with_file_name(name)
+    }
+}
+
+impl StorageBackend for FileBackend {
+    fn read(&self) -> Result<Option<(Vec<u8>, Version)>> {
+        match fs::read(&self.path) {
+            Ok(data) => {
+                let version = checksum(&data);
+                Ok(Some((data, version)))
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn write(&self, data: &[u8], expected: Option<&Version>) -> Result<Version> {
+        let current = self.read()?.map(|(_, version)| version);
+        if current.as_ref() != expected {
+            return Err(Error::Conflict);
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let temporary = self.sibling(".tmp");
+        fs::write(&temporary, data)?;
+        fs::rename(&temporary, &self.path)?;
+        Ok(checksum(data))
+    }
+
+    fn lock(&self) -> Result<Lock> {
+        let path = self.sibling(".lock");
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let start = Instant::now();
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    let since = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default();
+                    if let Err(e) = writeln!(file, "{} {}", process::id(), since.as_secs()) {
+                        let _ = fs::remove_file(&path);
+                        return Err(e.into());
+                    }
+                    break;
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    if is_stale(&path) {
+                        let _ = fs::remove_file(&path);
+                        continue;
+                    }
+                    if start.elapsed() >= LOCK_TIMEOUT {
+                        return Err(Error::StorageLocked(path.display().to_string()));
+                    }
+                    thread::sleep(Duration::from_millis(50));
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(Lock::new(move || {
+            let _ = fs::remove_file(path);
+        }))
+    }
+
+    fn exists(&self) -> Result<bool> {
+        Ok(self.path.exists())
+    }
+}
+
+#[derive(Debug, Default)]
+struct Memory {
+    data: Option<Vec<u8>>,
+    version: u64,
+    locked: bool,
+}
+
+/// Keeps the storage in memory, meant for tests. Clones share the same storage.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryBackend {
+    memory: Arc<Mutex<Memory>>,
+}
+
+impl MemoryBackend {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn with<T>(&self, f: impl FnOnce(&mut Memory) -> T) -> T {
+        f(&mut self.memory.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+impl StorageBackend for MemoryBackend {
+    fn read(&self) -> Result<Option<(Vec<u8>, Version)>> {
+        Ok(self.with(|memory| {
+            let version = Version(memory.version.to_string());
+            memory.data.clone().map(|data| (data, version))
+        }))
+    }
+
+    fn write(&self, data: &[u8], expected: Option<&Version>) -> Result<Version> {
+        self.with(|memory| {
+            let current = memory
+                .data
+                .as_ref()
+                .map(|_| Version(memory.version.to_string()));
+            if current.as_ref() != expected {
+                return Err(Error::Conflict);
+            }
+            memory.data = Some(data.to_vec());
+            memory.version += 1;
+            Ok(Version(memory.version.to_string()))
+        })
+    }
+
+    fn lock(&self) -> Result<Lock> {
+        self.with(|memory| {
+            if memory.locked {
+                return Err(Error::StorageLocked("in memory".to_string()));
+            }
+            memory.locked = true;
+            Ok(())
+        })?;
+        let memory = Arc::clone(&self.memory);
+        Ok(Lock::new(move || {
+            memory.lock().unwrap_or_else(PoisonError::into_inner).locked = false;
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compare_and_swap(backend: &dyn StorageBackend) {
+        assert!(!backend.exists().unwrap());
+        assert!(backend.read().unwrap().is_none());
+        let first = backend.write(b"one", None).unwrap();
+        assert!(backend.exists().unwrap());
+        assert!(matches!(backend.write(b"two", None), Err(Error::Conflict)));
+
+        let second = backend.write(b"two", Some(&first)).unwrap();
+        assert_ne!(first, second);
+        assert!(matches!(
+            backend.write(b"three", Some(&first)),
+            Err(Error::Conflict)
+        ));
+        assert_eq!(backend.read().unwrap(), Some((b"two".to_vec(), second)));
+    }
+
+    fn lock(backend: &dyn StorageBackend) {
+        let lock = backend.lock().unwrap();
+        assert!(matches!(backend.lock(), Err(Error::StorageLocked(_))));
+        drop(lock);
+        backend.lock().unwrap();
+    }
+
+    #[test]
+    fn test_file_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = FileBackend::new(dir.path().join("store").join("entries.toml.age"));
+        compare_and_swap(&backend);
+        lock(&backend);
+        assert!(!dir
+            .path()
+            .join("store")
+            .join("entries.toml.age.lock")
+            .exists());
+    }
+
+    #[test]
+    fn test_stale_file_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = FileBackend::new(dir.path().join("entries.toml.age"));
+        let path = dir.path().join("entries.toml.age.lock");
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        // held by this process a moment ago
+        fs::write(&path, format!("{} {}\n", process::id(), now)).unwrap();
+        assert!(matches!(backend.lock(), Err(Error::StorageLocked(_))));
+
+        // held by this process, but for too long
+        fs::write(
+            &path,
+            format!("{} {}\n", process::id(), now - 2 * STALE_LOCK_AGE.as_secs()),
+        )
+        .unwrap();
+        drop(backend.lock().unwrap());
+
+        // held by a process which doesn't exist
+        #[cfg(unix)]
+        {
+            fs::write(&path, format!("{} {}\n", i32::MAX, now)).unwrap();
+            let lock = backend.lock().unwrap();
+            assert!(fs::read_to_string(&path)
+                .unwrap()
+                .starts_with(&format!("{} ", process::id())));
+            drop(lock);
+        }
+
+        // an empty lock file is taken by its age
+        let file = fs::File::create(&path).unwrap();
+        assert!(matches!(backend.lock(), Err(Error::StorageLocked(_))));
+        file.set_modified(SystemTime::now() - 2 * STALE_LOCK_AGE)
+            .unwrap();
+        drop(backend.lock().unwrap());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_memory_backend() {
+        let backend = MemoryBackend::new();
+        compare_and_swap(&backend);
+        lock(&backend);
+    }
+}
@@ -0,0 +1,191 @@
+use std::io::Read;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use secrecy::{ExposeSecret, Secret};
+
+use super::{Lock, StorageBackend, Version};
+use crate::config::WebDavConfig;
+use crate::{Error, Result};
+
+/// User name and password for HTTP basic authentication
+#[derive(Debug, Clone)]
+pub struct BasicAuth {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+impl BasicAuth {
+    /// Takes the user name from `webdav.username` and the password from what
+    /// `webdav.password_command` prints, `None` if there is no user name
+    pub fn from_config(config: &WebDavConfig) -> Result<Option<Self>> {
+        let Some(username) = config.username.clone() else {
+            return Ok(None);
+        };
+        let command = config
+            .password_command
+            .as_deref()
+            .ok_or_else(|| anyhow!("webdav.username is set but webdav.password_command isn't"))?;
+        Ok(Some(Self {
+            username,
+            password: crate::secret_from_command(command, "WebDAV password")?,
+        }))
+    }
+
+    fn header(&self) -> String {
+        let credentials = format!("{}:{}", self.username, self.password.expose_secret());
+        format!("Basic {}", base64::encode(credentials))
+    }
+}
+
+/// Keeps the storage as a file on a `WebDAV` server, like Nextcloud.
+///
+/// The version is the `ETag` of the file, writes are conditional (`If-Match` and
+/// `If-None-Match`) so they fail instead of overwriting someone else's changes.
+#[derive(Debug)]
+pub struct WebDavBackend {
+    url: String,
+    auth: Option<BasicAuth>,
+    agent: ureq::Agent,
+}
+
+impl WebDavBackend {
+    pub fn new(config: &WebDavConfig, auth: Option<BasicAuth>) -> Result<Self> {
+        let url = config.url.clone().ok_or_else(|| {
+            anyhow!("the webdav backend needs the URL of the storage file, set webdav.url")
+        })?;
+        if !url.starts_with("https://") && !url.starts_with("http://") {
+            return Err(anyhow!("invalid webdav.url: {}", url).into());
+        }
+        Ok(Self {
+            url,
+            auth,
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(30))
+                .build(),
+        })
+    }
+
+    /// Sends a request for the storage file, responses with error statuses are returned
+    /// as well
+    fn send(&self, method: &str, headers: &[(&str, &str)], body: &[u8]) -> Result<ureq::Response> {
+        let mut request = self.agent.request(method, &self.url);
+        if let Some(auth) = &self.auth {
+            request = request.set("Authorization", &auth.header());
+        }
+        for (name, value) in headers {
+            request = request.set(name, value);
+        }
+        match request.send_bytes(body) {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => Ok(response),
+            Err(e) => Err(anyhow!("failed to reach {}: {}", self.url, e).into()),
+        }
+    }
+
+    fn failure(&self, method: &str, response: &ureq::Response) -> Error {
+        match response.status() {
+            401 => anyhow!(
+                "the WebDAV server rejected the credentials for {}, check webdav.username and \
+                 webdav.password_command",
+                self.url
+            ),
+            // the parent collection is missing
+            409 => anyhow!(
+                "the folder of {} doesn't exist on the WebDAV server",
+                self.url
+            ),
+            status => anyhow!(
+                "{} {} failed: {} {}",
+                method,
+                self.url,
+                status,
+                response.status_text()
+            ),
+        }
+        .into()
+    }
+
+    fn etag(&self, response: &ureq::Response) -> Result<Version> {
+        response
+            .header("etag")
+            .map(|etag| Version(etag.to_string()))
+            .ok_or_else(|| anyhow!("the WebDAV server sent no ETag for {}", self.url).into())
+    }
+}
+
+impl StorageBackend for WebDavBackend {
+    fn read(&self) -> Result<Option<(Vec<u8>, Version)>> {
+        let response = self.send("GET", &[], &[])?;
+        match response.status() {
+            200 => {
+                let etag = self.etag(&response)?;
+                let mut data = vec![];
+                response.into_reader().read_to_end(&mut data)?;
+                Ok(Some((data, etag)))
+            }
+            404 => Ok(None),
+            _ => Err(self.failure("GET", &response)),
+        }
+    }
+
+    fn write(&self, data: &[u8], expected: Option<&Version>) -> Result<Version> {
+        let condition = expected.map_or(("If-None-Match", "*"), |version| {
+            ("If-Match", version.0.as_str())
+        });
+        let response = self.send("PUT", &[condition], data)?;
+        match response.status() {
+            200 | 201 | 204 if response.header("etag").is_some() => self.etag(&response),
+            // not every server returns the ETag of what was written
+            200 | 201 | 204 => {
+                let response = self.send("HEAD", &[], &[])?;
+                match response.status() {
+                    200 => self.etag(&response),
+                    _ => Err(self.failure("HEAD", &response)),
+                }
+            }
+            412 => Err(Error::Conflict),
+            _ => Err(self.failure("PUT", &response)),
+        }
+    }
+
+    /// Not every `WebDAV` server supports locks, the conditional writes make sure nothing
+    /// gets overwritten
+    fn lock(&self) -> Result<Lock> {
+        Ok(Lock::new(|| {}))
+    }
+
+    fn exists(&self) -> Result<bool> {
+        let response = self.send("HEAD", &[], &[])?;
+        match response.status() {
+            200 => Ok(true),
+            404 => Ok(false),
+            _ => Err(self.failure("HEAD", &response)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_basic_auth() {
+        let config = |username: Option<&str>, command: Option<&str>| WebDavConfig {
+            url: None,
+            username: username.map(str::to_string),
+            password_command: command.map(str::to_string),
+        };
+        assert!(BasicAuth::from_config(&config(None, None))
+            .unwrap()
+            .is_none());
+        assert!(BasicAuth::from_config(&config(Some("chris"), None)).is_err());
+        assert!(BasicAuth::from_config(&config(Some("chris"), Some("false"))).is_err());
+
+        let auth = BasicAuth::from_config(&config(Some("chris"), Some("echo open sesame")))
+            .unwrap()
+            .unwrap();
+        assert_eq!(auth.password.expose_secret(), "open sesame");
+        assert_eq!(auth.header(), "Basic Y2hyaXM6b3BlbiBzZXNhbWU=");
+    }
+}
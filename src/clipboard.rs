@@ -0,0 +1,567 @@
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+#[cfg(any(test, not(target_os = "linux")))]
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use clipboard::ClipboardProvider;
+
+use passage::config::{ClipboardBackend, ClipboardConfig};
+
+/// Something which can hold the secret until it gets pasted somewhere else
+pub trait Backend {
+    /// Returns the current contents, `None` if they can't be read
+    fn get(&mut self) -> Result<Option<String>>;
+    fn set(&mut self, contents: &str) -> Result<()>;
+}
+
+/// Any clipboard supported by the `clipboard` crate (X11 selections, mac OS, Windows)
+struct Provider<P: ClipboardProvider>(P);
+
+impl<P: ClipboardProvider> Provider<P> {
+    fn new() -> Result<Self> {
+        Ok(Self(P::new().map_err(|e| {
+            anyhow!("failed to initialize clipboard provider: {}", e)
+        })?))
+    }
+}
+
+impl<P: ClipboardProvider> Backend for Provider<P> {
+    fn get(&mut self) -> Result<Option<String>> {
+        Ok(self.0.get_contents().ok())
+    }
+
+    fn set(&mut self, contents: &str) -> Result<()> {
+        self.0
+            .set_contents(contents.to_owned())
+            .map_err(|e| anyhow!("failed to copy to clipboard: {}", e))
+    }
+}
+
+/// Pipes the contents into and reads them back from external programs
+struct External {
+    copy: Vec<String>,
+    paste: Option<Vec<String>>,
+    clear: Option<Vec<String>>,
+}
+
+impl External {
+    fn from_config(config: &ClipboardConfig) -> Result<Self> {
+        let copy = config
+            .copy_command
+            .as_ref()
+            .ok_or_else(|| anyhow!("clipboard.copy_command is not set"))?;
+        Ok(Self {
+            copy: split_command(copy),
+            paste: config.paste_command.as_deref().map(split_command),
+            clear: None,
+        })
+    }
+
+    /// Uses `wl-copy` and `wl-paste` from wl-clipboard
+    fn wayland(primary: bool) -> Self {
+        let with_primary = |args: &[&str]| {
+            let mut command: Vec<String> = args.iter().map(|a| (*a).to_string()).collect();
+            if primary {
+                command.push("--primary".to_string());
+            }
+            command
+        };
+        Self {
+            copy: with_primary(&["wl-copy"]),
+            paste: Some(with_primary(&["wl-paste", "--no-newline"])),
+            clear: Some(with_primary(&["wl-copy", "--clear"])),
+        }
+    }
+}
+
+impl Backend for External {
+    fn get(&mut self) -> Result<Option<String>> {
+        let Some(command) = &self.paste else {
+            return Ok(None);
+        };
+        let output = command_for(command)?
+            .stderr(Stdio::null())
+            .output()
+            .map_err(|e| anyhow!("failed to run {}: {}", command[0], e))?;
+        if output.status.success() {
+            Ok(Some(String::from_utf8_lossy(&output.stdout).into_owned()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn set(&mut self, contents: &str) -> Result<()> {
+        let (command, input) = match &self.clear {
+            Some(clear) if contents.is_empty() => (clear, None),
+            _ => (&self.copy, Some(contents)),
+        };
+        let mut child = command_for(command)?
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| anyhow!("failed to run {}: {}", command[0], e))?;
+        if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+            stdin.write_all(input.as_bytes())?;
+        }
+        if child.wait()?.success() {
+            Ok(())
+        } else {
+            Err(anyhow!("{} failed to copy to clipboard", command[0]))
+        }
+    }
+}
+
+/// Multiplexers which need OSC 52 sequences wrapped to pass them on to the terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Multiplexer {
+    Tmux,
+    Screen,
+}
+
+/// Sets the clipboard of the terminal emulator with an OSC 52 escape sequence, which also
+/// works in SSH sessions. The clipboard can't be read back this way.
+struct Osc52 {
+    selection: char,
+    multiplexer: Option<Multiplexer>,
+}
+
+impl Osc52 {
+    fn new(primary: bool) -> Self {
+        let multiplexer = if std::env::var_os("TMUX").is_some() {
+            Some(Multiplexer::Tmux)
+        } else if std::env::var("TERM").is_ok_and(|t| t.starts_with("screen")) {
+            Some(Multiplexer::Screen)
+        } else {
+            None
+        };
+        Self {
+            selection: if primary { 'p' } else { 'c' },
+            multiplexer,
+        }
+    }
+
+    /// Returns the escape sequence setting the selection to `contents`, an empty string
+    /// clears the selection
+    fn sequence(&self, contents: &str) -> String {
+        let payload = if contents.is_empty() {
+            // anything which isn't valid base64 clears the selection
+            "!".to_string()
+        } else {
+            base64::encode(contents)
+        };
+        let sequence = format!("\x1b]52;{};{}\x07", self.selection, payload);
+        match self.multiplexer {
+            None => sequence,
+            Some(Multiplexer::Tmux) => {
+                format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b"))
+            }
+            // screen limits the length of device control strings, so send it in chunks
+            Some(Multiplexer::Screen) => {
+                let mut wrapped = String::new();
+                for chunk in sequence.as_bytes().chunks(76) {
+                    wrapped.push_str("\x1bP");
+                    wrapped.push_str(&String::from_utf8_lossy(chunk));
+                    wrapped.push_str("\x1b\\");
+                }
+                wrapped
+            }
+        }
+    }
+}
+
+impl Backend for Osc52 {
+    fn get(&mut self) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    fn set(&mut self, contents: &str) -> Result<()> {
+        let sequence = self.sequence(contents);
+        if let Ok(mut tty) = OpenOptions::new().write(true).open("/dev/tty") {
+            tty.write_all(sequence.as_bytes())?;
+        } else {
+            let mut stdout = io::stdout();
+            stdout.write_all(sequence.as_bytes())?;
+            stdout.flush()?;
+        }
+        Ok(())
+    }
+}
+
+fn split_command(command: &str) -> Vec<String> {
+    command.split_whitespace().map(str::to_string).collect()
+}
+
+fn command_for(command: &[String]) -> Result<Command> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| anyhow!("empty clipboard command"))?;
+    let mut command = Command::new(program);
+    command.args(args);
+    Ok(command)
+}
+
+/// Turns `auto` into the backend matching the current session
+#[cfg(target_os = "linux")]
+fn resolve(config: &ClipboardConfig) -> Result<ClipboardBackend> {
+    match config.backend {
+        ClipboardBackend::Auto => {
+            if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+                Ok(ClipboardBackend::Wayland)
+            } else if std::env::var_os("DISPLAY").is_some() {
+                Ok(ClipboardBackend::X11)
+            } else if std::env::var_os("SSH_TTY").is_some()
+                || std::env::var_os("SSH_CONNECTION").is_some()
+            {
+                Ok(ClipboardBackend::Osc52)
+            } else {
+                Err(anyhow!(
+                    "no clipboard found (neither WAYLAND_DISPLAY nor DISPLAY is set), set \
+                     clipboard.backend = \"osc52\" to use the clipboard of the terminal"
+                ))
+            }
+        }
+        backend => Ok(backend),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+const fn resolve(config: &ClipboardConfig) -> Result<ClipboardBackend> {
+    Ok(config.backend)
+}
+
+fn open(backend: ClipboardBackend, config: &ClipboardConfig) -> Result<Box<dyn Backend>> {
+    match backend {
+        ClipboardBackend::Wayland => Ok(Box::new(External::wayland(config.primary))),
+        ClipboardBackend::Command => Ok(Box::new(External::from_config(config)?)),
+        ClipboardBackend::Osc52 => Ok(Box::new(Osc52::new(config.primary))),
+        #[cfg(target_os = "linux")]
+        ClipboardBackend::X11 => {
+            use clipboard::x11_clipboard::{Clipboard, Primary, X11ClipboardContext};
+            if config.primary {
+                Ok(Box::new(Provider::<X11ClipboardContext<Primary>>::new()?))
+            } else {
+                Ok(Box::new(Provider::<X11ClipboardContext<Clipboard>>::new()?))
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        ClipboardBackend::X11 => Err(anyhow!("the x11 clipboard is only supported on Linux")),
+        #[cfg(target_os = "linux")]
+        ClipboardBackend::Auto => resolve(config).and_then(|b| open(b, config)),
+        #[cfg(not(target_os = "linux"))]
+        ClipboardBackend::Auto => Ok(Box::new(Provider::<clipboard::ClipboardContext>::new()?)),
+    }
+}
+
+/// Copies `secret` into the clipboard and waits for `clip_time`, after which the previous
+/// contents are put back, unless the clipboard got overwritten by something else meanwhile.
+/// `copied` gets called as soon as the secret is in the clipboard, errors before that mean
+/// it couldn't be copied.
+fn copy_and_restore(
+    backend: &mut dyn Backend,
+    secret: &str,
+    clip_time: Duration,
+    copied: impl FnOnce(),
+) -> Result<()> {
+    let previous = backend.get()?.unwrap_or_default();
+    backend.set(secret)?;
+    copied();
+
+    thread::sleep(clip_time);
+
+    if backend.get()?.is_none_or(|current| current == secret) {
+        backend.set(&previous)?;
+    }
+    Ok(())
+}
+
+/// A secret copied by `copy_quietly`, which gets cleared from the clipboard after
+/// `clip_time` seconds
+#[must_use]
+pub struct Copied {
+    pub clip_time: u64,
+    /// The thread clearing the clipboard, if that happens in this process
+    restore: Option<JoinHandle<()>>,
+}
+
+impl Copied {
+    /// Whether the clipboard still has to be cleared by this process
+    pub fn is_pending(&self) -> bool {
+        self.restore
+            .as_ref()
+            .is_some_and(|restore| !restore.is_finished())
+    }
+
+    /// Waits until the clipboard got cleared, the process must not exit before if the
+    /// clipboard gets cleared by one of its threads
+    pub fn wait(self) {
+        if let Some(restore) = self.restore {
+            let _ = restore.join();
+        }
+    }
+}
+
+/// Copies `secret` to the clipboard and tells the user when it gets cleared
+pub fn copy(config: &ClipboardConfig, secret: &str, clip_time: u64) -> Result<()> {
+    let copied = copy_quietly(config, secret, clip_time)?;
+    println!(
+        "Copied to clipboard, clearing in {} seconds",
+        copied.clip_time
+    );
+    copied.wait();
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+use fork::{fork, Fork};
+#[cfg(target_os = "linux")]
+use std::io::Read;
+
+/// Copies `secret` to the clipboard without printing anything, the clipboard is restored
+/// by a forked child process after `clip_time` seconds (X11 clipboards are only readable
+/// while their owner lives). The child reports whether copying worked through a pipe and
+/// never writes to the terminal, which might be in use by the TUI. It exits once it's done,
+/// so the caller keeps running in the parent only.
+#[cfg(target_os = "linux")]
+pub fn copy_quietly(config: &ClipboardConfig, secret: &str, clip_time: u64) -> Result<Copied> {
+    let backend = resolve(config)?;
+    let (mut reader, writer) = io::pipe()?;
+    match fork() {
+        Ok(Fork::Child) => {
+            drop(reader);
+            let mut writer = Some(writer);
+            let result = open(backend, config).and_then(|mut backend| {
+                copy_and_restore(
+                    backend.as_mut(),
+                    secret,
+                    Duration::from_secs(clip_time),
+                    || drop(writer.take()),
+                )
+            });
+            if let (Err(e), Some(mut writer)) = (&result, writer) {
+                let _ = write!(writer, "{:#}", e);
+            }
+            std::process::exit(i32::from(result.is_err()));
+        }
+        Err(_) => Err(anyhow!("Failed to fork()")),
+        Ok(_) => {
+            drop(writer);
+            let mut error = String::new();
+            reader.read_to_string(&mut error)?;
+            if error.is_empty() {
+                Ok(Copied {
+                    clip_time,
+                    restore: None,
+                })
+            } else {
+                Err(anyhow!(error))
+            }
+        }
+    }
+}
+
+/// Copies `secret` to the clipboard without printing anything, the clipboard is restored
+/// by a thread after `clip_time` seconds, see `Copied::wait`
+#[cfg(not(target_os = "linux"))]
+pub fn copy_quietly(config: &ClipboardConfig, secret: &str, clip_time: u64) -> Result<Copied> {
+    let backend = resolve(config)?;
+    let config = config.clone();
+    copy_in_background(
+        move || open(backend, &config),
+        secret.to_string(),
+        clip_time,
+    )
+}
+
+/// Runs `copy_and_restore` on a thread, with the backend `open` returns (clipboards can't
+/// always be moved between threads). Returns once the secret is in the clipboard.
+#[cfg(any(test, not(target_os = "linux")))]
+fn copy_in_background(
+    open: impl FnOnce() -> Result<Box<dyn Backend>> + Send + 'static,
+    secret: String,
+    clip_time: u64,
+) -> Result<Copied> {
+    let (done, copied) = mpsc::channel();
+    let restore = thread::spawn(move || {
+        let mut done = Some(done);
+        let result = open().and_then(|mut backend| {
+            copy_and_restore(
+                backend.as_mut(),
+                &secret,
+                Duration::from_secs(clip_time),
+                || {
+                    if let Some(done) = done.take() {
+                        let _ = done.send(Ok(()));
+                    }
+                },
+            )
+        });
+        if let (Err(e), Some(done)) = (result, done) {
+            let _ = done.send(Err(e));
+        }
+    });
+    copied
+        .recv()
+        .map_err(|_| anyhow!("the clipboard thread stopped"))??;
+    Ok(Copied {
+        clip_time,
+        restore: Some(restore),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// Stands in for a real clipboard, optionally simulating the user copying `overwrite`
+    /// while the secret is in the clipboard
+    #[derive(Default)]
+    struct Fake {
+        contents: Option<String>,
+        overwrite: Option<String>,
+        history: Vec<String>,
+    }
+
+    impl Backend for Fake {
+        fn get(&mut self) -> Result<Option<String>> {
+            if !self.history.is_empty() {
+                if let Some(o) = self.overwrite.take() {
+                    self.contents = Some(o);
+                }
+            }
+            Ok(self.contents.clone())
+        }
+
+        fn set(&mut self, contents: &str) -> Result<()> {
+            self.history.push(contents.to_string());
+            self.contents = Some(contents.to_string());
+            Ok(())
+        }
+    }
+
+    /// Lets the test look at a `Fake` which moved to the clipboard thread
+    struct Shared(Arc<Mutex<Fake>>);
+
+    impl Backend for Shared {
+        fn get(&mut self) -> Result<Option<String>> {
+            self.0.lock().unwrap().get()
+        }
+
+        fn set(&mut self, contents: &str) -> Result<()> {
+            self.0.lock().unwrap().set(contents)
+        }
+    }
+
+    #[test]
+    fn test_restore_previous() {
+        let mut fake = Fake {
+            contents: Some("previous".to_string()),
+            ..Fake::default()
+        };
+        copy_and_restore(&mut fake, "secret", Duration::from_secs(0), || ()).unwrap();
+        assert_eq!(fake.history, vec!["secret", "previous"]);
+        assert_eq!(fake.contents.unwrap(), "previous");
+    }
+
+    #[test]
+    fn test_clear_empty_clipboard() {
+        let mut fake = Fake::default();
+        copy_and_restore(&mut fake, "secret", Duration::from_secs(0), || ()).unwrap();
+        assert_eq!(fake.history, vec!["secret", ""]);
+    }
+
+    #[test]
+    fn test_keep_overwritten_contents() {
+        let mut fake = Fake {
+            contents: Some("previous".to_string()),
+            overwrite: Some("copied by the user".to_string()),
+            ..Fake::default()
+        };
+        copy_and_restore(&mut fake, "secret", Duration::from_secs(0), || ()).unwrap();
+        assert_eq!(fake.history, vec!["secret"]);
+        assert_eq!(fake.contents.unwrap(), "copied by the user");
+    }
+
+    #[test]
+    fn test_copy_in_background() {
+        let fake = Arc::new(Mutex::new(Fake {
+            contents: Some("previous".to_string()),
+            ..Fake::default()
+        }));
+        let shared = Shared(Arc::clone(&fake));
+        let copied = copy_in_background(
+            move || Ok(Box::new(shared) as Box<dyn Backend>),
+            "secret".to_string(),
+            0,
+        )
+        .unwrap();
+        assert_eq!(copied.clip_time, 0);
+        copied.wait();
+        assert_eq!(fake.lock().unwrap().history, vec!["secret", "previous"]);
+
+        let failed = copy_in_background(|| Err(anyhow!("no clipboard")), "secret".to_string(), 0);
+        assert_eq!(
+            failed.err().map(|e| e.to_string()),
+            Some("no clipboard".to_string())
+        );
+    }
+
+    #[test]
+    fn test_command_backend() {
+        let config = ClipboardConfig {
+            backend: ClipboardBackend::Command,
+            copy_command: Some("xclip -selection clipboard".to_string()),
+            ..ClipboardConfig::default()
+        };
+        let external = External::from_config(&config).unwrap();
+        assert_eq!(external.copy, vec!["xclip", "-selection", "clipboard"]);
+        assert!(external.paste.is_none());
+
+        let config = ClipboardConfig {
+            backend: ClipboardBackend::Command,
+            ..ClipboardConfig::default()
+        };
+        assert!(External::from_config(&config).is_err());
+    }
+
+    #[test]
+    fn test_osc52_sequences() {
+        let mut osc52 = Osc52 {
+            selection: 'c',
+            multiplexer: None,
+        };
+        assert_eq!(osc52.sequence("secret"), "\x1b]52;c;c2VjcmV0\x07");
+        assert_eq!(osc52.sequence(""), "\x1b]52;c;!\x07");
+
+        osc52.multiplexer = Some(Multiplexer::Tmux);
+        assert_eq!(
+            osc52.sequence("secret"),
+            "\x1bPtmux;\x1b\x1b]52;c;c2VjcmV0\x07\x1b\\"
+        );
+
+        osc52.selection = 'p';
+        osc52.multiplexer = Some(Multiplexer::Screen);
+        let long = "a".repeat(200);
+        let sequence = osc52.sequence(&long);
+        assert!(sequence.starts_with("\x1bP\x1b]52;p;YWFh"));
+        assert_eq!(sequence.matches("\x1bP").count(), 4);
+        assert_eq!(
+            sequence.replace("\x1bP", "").replace("\x1b\\", ""),
+            format!("\x1b]52;p;{}\x07", base64::encode(&long))
+        );
+    }
+
+    #[test]
+    fn test_wayland_primary() {
+        let wayland = External::wayland(true);
+        assert_eq!(wayland.copy, vec!["wl-copy", "--primary"]);
+        assert_eq!(
+            wayland.clear.unwrap(),
+            vec!["wl-copy", "--clear", "--primary"]
+        );
+    }
+}
@@ -0,0 +1,107 @@
+use std::io::Write;
+
+use anyhow::Result;
+use structopt::clap::{App, Shell};
+
+/// Completes entry and vault names by calling `passage __complete`, everything else is
+/// left to the completion function generated by clap
+const BASH: &str = r#"
+_passage_names() {
+    local i names words=() vault=()
+    local cur="${COMP_WORDS[COMP_CWORD]}" prev="${COMP_WORDS[COMP_CWORD-1]}"
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        case "${COMP_WORDS[i]}" in
+            --vault) vault=(--vault "${COMP_WORDS[i+1]}"); ((i++)) ;;
+            --output|--out|--from|--to|--sequence|-s|--username|-u|--autotype|--clip-time|--prefer) ((i++)) ;;
+            -*) ;;
+            *) words+=("${COMP_WORDS[i]}") ;;
+        esac
+    done
+    case "$prev" in
+        --vault|--from|--to) names=vaults ;;
+        *)
+            case "${words[*]}" in
+                show|edit|type|remove|mv|cp) names=entries ;;
+                "vault use"|"vault remove") names=vaults ;;
+            esac
+            ;;
+    esac
+    if [[ -n $names && $cur != -* ]]; then
+        COMPREPLY=($(compgen -W "$(passage __complete "$names" "${vault[@]}" 2>/dev/null)" -- "$cur"))
+    else
+        _passage "$@"
+    fi
+}
+
+complete -F _passage_names -o bashdefault -o default passage
+"#;
+
+/// Same as `BASH`, it replaces the call of the generated `_passage` at the end
+const ZSH: &str = r#"
+_passage_names() {
+    local i names
+    local -a positional vault candidates
+    for ((i = 2; i < CURRENT; i++)); do
+        case $words[i] in
+            --vault) vault=(--vault $words[i+1]); ((i++)) ;;
+            --output|--out|--from|--to|--sequence|-s|--username|-u|--autotype|--clip-time|--prefer) ((i++)) ;;
+            -*) ;;
+            *) positional+=($words[i]) ;;
+        esac
+    done
+    case $words[CURRENT-1] in
+        --vault|--from|--to) names=vaults ;;
+        *)
+            case "$positional" in
+                show|edit|type|remove|mv|cp) names=entries ;;
+                "vault use"|"vault remove") names=vaults ;;
+            esac
+            ;;
+    esac
+    if [[ -n $names && $words[CURRENT] != -* ]]; then
+        candidates=(${(f)"$(passage __complete $names $vault 2>/dev/null)"})
+        compadd -a candidates
+    else
+        _passage "$@"
+    fi
+}
+
+_passage_names "$@"
+"#;
+
+const FISH: &str = r#"
+function __passage_complete
+    set -l tokens (commandline -opc)
+    set -l vault
+    if set -l i (contains -i -- --vault $tokens)
+        set vault --vault $tokens[(math $i + 1)]
+    end
+    passage __complete $argv $vault 2>/dev/null
+end
+complete -c passage -n "__fish_seen_subcommand_from show edit type remove mv cp" -f -a "(__passage_complete entries)"
+complete -c passage -n "__fish_seen_subcommand_from vault; and __fish_seen_subcommand_from use remove" -f -a "(__passage_complete vaults)"
+complete -c passage -l vault -x -a "(__passage_complete vaults)"
+complete -c passage -n "__fish_seen_subcommand_from cp" -l from -x -a "(__passage_complete vaults)"
+complete -c passage -n "__fish_seen_subcommand_from cp" -l to -x -a "(__passage_complete vaults)"
+"#;
+
+/// Writes the completion script for `shell` to `out`. On top of what clap generates, the
+/// scripts for bash, zsh and fish complete entry and vault names.
+pub fn generate(mut app: App, shell: Shell, out: &mut impl Write) -> Result<()> {
+    let mut generated = vec![];
+    app.gen_completions_to("passage", shell, &mut generated);
+    let generated = String::from_utf8(generated)?;
+    match shell {
+        Shell::Bash => write!(out, "{}{}", generated, BASH)?,
+        Shell::Zsh => {
+            let generated = generated.trim_end();
+            let generated = generated
+                .strip_suffix("_passage \"$@\"")
+                .unwrap_or(generated);
+            write!(out, "{}{}", generated, ZSH)?;
+        }
+        Shell::Fish => write!(out, "{}{}", generated, FISH)?,
+        _ => out.write_all(generated.as_bytes())?,
+    }
+    Ok(())
+}
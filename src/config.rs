@@ -0,0 +1,650 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use directories_next::ProjectDirs;
+use serde::{Deserialize, Serialize};
+
+/// Keys which can be read and written with `passage config`, each of them can also be
+/// overridden with an environment variable (see `env_var`)
+pub const KEYS: &[&str] = &[
+    "storage_folder",
+    "vault",
+    "keyring",
+    "passphrase_command",
+    "clip_time",
+    "show_on",
+    "clipboard.backend",
+    "clipboard.primary",
+    "clipboard.copy_command",
+    "clipboard.paste_command",
+    "autotype.backend",
+    "autotype.sequence",
+    "hooks.enabled",
+    "hooks.timeout",
+    "hooks.pre_load.on_failure",
+    "hooks.pre_load.timeout",
+    "hooks.post_load.on_failure",
+    "hooks.post_load.timeout",
+    "hooks.pre_save.on_failure",
+    "hooks.pre_save.timeout",
+    "hooks.post_save.on_failure",
+    "hooks.post_save.timeout",
+    "backend",
+    "s3.endpoint",
+    "s3.region",
+    "s3.bucket",
+    "s3.key",
+    "webdav.url",
+    "webdav.username",
+    "webdav.password_command",
+];
+
+/// Where the password should go when running `passage show`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShowOn {
+    Clipboard,
+    Screen,
+}
+
+/// Where the storage file is kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    /// In the storage folder
+    File,
+    /// In an S3 compatible bucket
+    S3,
+    /// On a `WebDAV` server, like Nextcloud
+    WebDav,
+}
+
+/// Which clipboard implementation to use, `auto` picks one based on the session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClipboardBackend {
+    Auto,
+    Wayland,
+    X11,
+    Command,
+    /// Terminal escape sequences, for remote sessions without a display
+    Osc52,
+}
+
+/// Which tool types into the focused window, `auto` picks one based on the session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AutotypeBackend {
+    Auto,
+    Xdotool,
+    Ydotool,
+    Wtype,
+}
+
+/// What happens when a hook exits with a non-zero status
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnFailure {
+    /// Abort the command
+    Fatal,
+    /// Print a warning and carry on
+    Warn,
+}
+
+/// User preferences, read from `config.toml` and the environment
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Folder containing the storage file and the hooks directory
+    pub storage_folder: Option<String>,
+    /// Name of the vault in use, its storage folder takes precedence over `storage_folder`
+    pub vault: Option<String>,
+    /// Named vaults, added with `passage vault add`
+    pub vaults: BTreeMap<String, VaultConfig>,
+    /// Where the storage file is kept
+    pub backend: BackendKind,
+    pub s3: S3Config,
+    pub webdav: WebDavConfig,
+    /// Whether the passphrase should be stored in the OS keyring
+    pub keyring: bool,
+    /// Prints the passphrase, it's used instead of the keyring and the prompt
+    pub passphrase_command: Option<String>,
+    /// Seconds after which the clipboard gets cleared
+    pub clip_time: u64,
+    /// Where `passage show` puts the password by default
+    pub show_on: ShowOn,
+    pub clipboard: ClipboardConfig,
+    pub autotype: AutotypeConfig,
+    pub hooks: HooksConfig,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct VaultConfig {
+    pub storage_folder: String,
+    /// Overrides `backend` for this vault
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<BackendKind>,
+    /// Overrides `s3` for this vault
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub s3: Option<S3Config>,
+    /// Overrides `webdav` for this vault
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webdav: Option<WebDavConfig>,
+    /// Prints the passphrase of this vault
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passphrase_command: Option<String>,
+}
+
+/// Bucket and object of the `s3` backend, credentials are taken from the environment
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct S3Config {
+    /// URL of the S3 API, defaults to the AWS endpoint of `region`
+    pub endpoint: Option<String>,
+    pub region: String,
+    pub bucket: Option<String>,
+    /// Name of the object holding the storage
+    pub key: String,
+}
+
+/// Location and credentials of the `webdav` backend
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebDavConfig {
+    /// URL of the storage file on the server
+    pub url: Option<String>,
+    pub username: Option<String>,
+    /// Program printing the password, so it doesn't have to be kept in the config file
+    pub password_command: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClipboardConfig {
+    pub backend: ClipboardBackend,
+    /// Use the primary selection instead of the clipboard (X11, Wayland and OSC 52)
+    pub primary: bool,
+    /// Program reading the secret from stdin, used by the `command` backend
+    pub copy_command: Option<String>,
+    /// Program printing the clipboard contents, used by the `command` backend
+    pub paste_command: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutotypeConfig {
+    pub backend: AutotypeBackend,
+    /// Sequence for entries which don't have their own
+    pub sequence: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HooksConfig {
+    /// Set to false to never run any hooks
+    pub enabled: bool,
+    /// Seconds after which a hook gets killed
+    pub timeout: u64,
+    pub pre_load: HookConfig,
+    pub post_load: HookConfig,
+    pub pre_save: HookConfig,
+    pub post_save: HookConfig,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HookConfig {
+    pub on_failure: OnFailure,
+    /// Overrides `hooks.timeout` for this hook
+    pub timeout: Option<u64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            storage_folder: None,
+            vault: None,
+            vaults: BTreeMap::new(),
+            backend: BackendKind::File,
+            s3: S3Config::default(),
+            webdav: WebDavConfig::default(),
+            keyring: true,
+            passphrase_command: None,
+            clip_time: 10,
+            show_on: ShowOn::Clipboard,
+            clipboard: ClipboardConfig::default(),
+            autotype: AutotypeConfig::default(),
+            hooks: HooksConfig::default(),
+        }
+    }
+}
+
+impl Default for S3Config {
+    fn default() -> Self {
+        Self {
+            endpoint: None,
+            region: "us-east-1".to_string(),
+            bucket: None,
+            key: crate::STORAGE_FILE.to_string(),
+        }
+    }
+}
+
+impl Default for ClipboardConfig {
+    fn default() -> Self {
+        Self {
+            backend: ClipboardBackend::Auto,
+            primary: false,
+            copy_command: None,
+            paste_command: None,
+        }
+    }
+}
+
+impl Default for AutotypeConfig {
+    fn default() -> Self {
+        Self {
+            backend: AutotypeBackend::Auto,
+            sequence: None,
+        }
+    }
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            timeout: 60,
+            pre_load: HookConfig::default(),
+            post_load: HookConfig::default(),
+            pre_save: HookConfig::default(),
+            post_save: HookConfig::default(),
+        }
+    }
+}
+
+impl Default for HookConfig {
+    fn default() -> Self {
+        Self {
+            on_failure: OnFailure::Fatal,
+            timeout: None,
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config file (if any) and applies overrides from the environment
+    pub fn load() -> Result<Self> {
+        let mut values = read_file()?;
+        for key in KEYS {
+            if let Ok(raw) = std::env::var(env_var(key)) {
+                insert(&mut values, key, typed_value(key, &raw))?;
+            }
+        }
+        values
+            .try_into()
+            .map_err(|e| anyhow!("invalid configuration: {}", e))
+    }
+
+    /// Returns a copy of this config which uses the vault `name`
+    pub fn for_vault(&self, name: &str) -> Result<Self> {
+        let mut config = self.clone();
+        config.use_vault(Some(name.to_string()))?;
+        Ok(config)
+    }
+
+    /// Switches to the vault `name`, or checks the configured one if there is no `name`
+    pub fn use_vault(&mut self, name: Option<String>) -> Result<()> {
+        if name.is_some() {
+            self.vault = name;
+        }
+        match &self.vault {
+            Some(name) if !self.vaults.contains_key(name) => {
+                Err(crate::Error::UnknownVault(name.clone()).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the settings of the vault in use, if there is one
+    fn vault_config(&self) -> Option<&VaultConfig> {
+        self.vault.as_ref().and_then(|name| self.vaults.get(name))
+    }
+
+    /// Returns the storage folder of the vault in use, if there is one
+    #[must_use]
+    pub fn vault_folder(&self) -> Option<&str> {
+        self.vault_config()
+            .map(|vault| vault.storage_folder.as_str())
+    }
+
+    /// Returns where the storage file is kept, taking the vault in use into account
+    #[must_use]
+    pub fn storage_backend(&self) -> BackendKind {
+        self.vault_config()
+            .and_then(|vault| vault.backend)
+            .unwrap_or(self.backend)
+    }
+
+    /// Returns the S3 settings, taking the vault in use into account
+    #[must_use]
+    pub fn s3_config(&self) -> &S3Config {
+        self.vault_config()
+            .and_then(|vault| vault.s3.as_ref())
+            .unwrap_or(&self.s3)
+    }
+
+    /// Returns the command printing the passphrase of the storage in use. Vaults only use
+    /// their own, as they have a passphrase of their own.
+    #[must_use]
+    pub fn passphrase_command(&self) -> Option<&str> {
+        match &self.vault {
+            Some(_) => self
+                .vault_config()
+                .and_then(|vault| vault.passphrase_command.as_deref()),
+            None => self.passphrase_command.as_deref(),
+        }
+    }
+
+    /// Returns the `WebDAV` settings, taking the vault in use into account
+    #[must_use]
+    pub fn webdav_config(&self) -> &WebDavConfig {
+        self.vault_config()
+            .and_then(|vault| vault.webdav.as_ref())
+            .unwrap_or(&self.webdav)
+    }
+}
+
+/// Returns the path to the config file, `PASSAGE_CONFIG` takes precedence over the default
+pub fn config_file() -> Result<String> {
+    std::env::var("PASSAGE_CONFIG").or_else(|_| {
+        ProjectDirs::from("", "", "passage")
+            .map(|pd| pd.config_dir().join("config.toml").display().to_string())
+            .ok_or_else(|| anyhow!("couldn't determine project config folder"))
+    })
+}
+
+/// Returns the name of the environment variable overriding `key`, e.g. `PASSAGE_CLIP_TIME`
+#[must_use]
+pub fn env_var(key: &str) -> String {
+    format!("PASSAGE_{}", key.replace('.', "_").to_uppercase())
+}
+
+/// Returns the value of `key` from the config file, falling back to the default
+pub fn get(key: &str) -> Result<String> {
+    check_key(key)?;
+    match lookup(&file_values()?, key) {
+        Some(toml::Value::String(s)) => Ok(s),
+        Some(v) => Ok(v.to_string()),
+        None => Err(anyhow!("{} is not set", key)),
+    }
+}
+
+/// Writes `value` for `key` into the config file, creating it if needed
+pub fn set(key: &str, value: &str) -> Result<()> {
+    check_key(key)?;
+    let value = typed_value(key, value);
+    check_value(key, &value).map_err(|e| anyhow!("invalid value for {}: {}", key, e))?;
+    let mut values = read_file()?;
+    insert(&mut values, key, value)?;
+    write_file(&values)
+}
+
+/// Removes `key` from the config file, so it goes back to its default
+pub fn unset(key: &str) -> Result<()> {
+    check_key(key)?;
+    let mut values = read_file()?;
+    let mut parts: Vec<&str> = key.split('.').collect();
+    let last = parts.pop().ok_or_else(|| anyhow!("empty config key"))?;
+    let table = parts
+        .into_iter()
+        .try_fold(&mut values, |v, part| v.get_mut(part))
+        .and_then(toml::Value::as_table_mut);
+    if let Some(table) = table {
+        table.remove(last);
+    }
+    write_file(&values)
+}
+
+/// Registers a vault named `name` which keeps its entries in `storage_folder`
+pub fn add_vault(name: &str, storage_folder: &str) -> Result<()> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(anyhow!(
+            "invalid vault name: {} (use letters, digits, - and _)",
+            name
+        ));
+    }
+    let mut values = read_file()?;
+    if values.get("vaults").and_then(|v| v.get(name)).is_some() {
+        return Err(anyhow!("vault already exists: {}", name));
+    }
+    let mut vault = toml::value::Table::new();
+    vault.insert(
+        "storage_folder".to_string(),
+        toml::Value::String(storage_folder.to_string()),
+    );
+    insert(
+        &mut values,
+        &format!("vaults.{}", name),
+        toml::Value::Table(vault),
+    )?;
+    write_file(&values)
+}
+
+/// Forgets the vault named `name`, its files are left alone
+pub fn remove_vault(name: &str) -> Result<()> {
+    let mut values = read_file()?;
+    let removed = values
+        .get_mut("vaults")
+        .and_then(toml::Value::as_table_mut)
+        .and_then(|vaults| vaults.remove(name));
+    if removed.is_none() {
+        return Err(crate::Error::UnknownVault(name.to_string()).into());
+    }
+    if values.get("vault").and_then(toml::Value::as_str) == Some(name) {
+        if let Some(table) = values.as_table_mut() {
+            table.remove("vault");
+        }
+    }
+    write_file(&values)
+}
+
+/// Returns all keys which have a value, together with that value
+pub fn list() -> Result<Vec<(String, String)>> {
+    let values = file_values()?;
+    Ok(KEYS
+        .iter()
+        .filter_map(|key| lookup(&values, key).map(|v| ((*key).to_string(), v.to_string())))
+        .collect())
+}
+
+/// Returns the defaults with the values of the config file on top. Unlike `Config::load`
+/// this doesn't check the values, so a broken config file can still be looked at.
+fn file_values() -> Result<toml::Value> {
+    let mut values = toml::Value::try_from(Config::default())?;
+    let file = read_file()?;
+    for key in KEYS {
+        if let Some(value) = lookup(&file, key) {
+            insert(&mut values, key, value)?;
+        }
+    }
+    Ok(values)
+}
+
+fn check_key(key: &str) -> Result<()> {
+    if KEYS.contains(&key) {
+        Ok(())
+    } else {
+        Err(anyhow!("unknown config key: {}", key))
+    }
+}
+
+/// Checks `value` on its own, so an invalid value of another key doesn't get in the way of
+/// fixing the config file
+fn check_value(key: &str, value: &toml::Value) -> Result<()> {
+    let mut values = toml::Value::Table(toml::value::Table::new());
+    insert(&mut values, key, value.clone())?;
+    let _: Config = values.try_into()?;
+    Ok(())
+}
+
+fn write_file(values: &toml::Value) -> Result<()> {
+    let path = config_file()?;
+    if let Some(parent) = Path::new(&path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, toml::to_string_pretty(values)?)?;
+    Ok(())
+}
+
+fn read_file() -> Result<toml::Value> {
+    let path = config_file()?;
+    if fs::metadata(&path).is_err() {
+        return Ok(toml::Value::Table(toml::value::Table::new()));
+    }
+    let contents = fs::read_to_string(&path)?;
+    contents
+        .parse()
+        .map_err(|e| anyhow!("failed to parse {}: {}", path, e))
+}
+
+/// Interprets `raw` as a TOML value, anything which doesn't parse is taken as a string
+fn parse_value(raw: &str) -> toml::Value {
+    format!("value = {}", raw)
+        .parse::<toml::Value>()
+        .ok()
+        .and_then(|v| v.get("value").cloned())
+        .unwrap_or_else(|| toml::Value::String(raw.to_string()))
+}
+
+/// Interprets `raw` as a value for `key`, so `123` or `true` stay a string for keys which
+/// take one
+fn typed_value(key: &str, raw: &str) -> toml::Value {
+    let value = parse_value(raw);
+    let string = toml::Value::String(raw.to_string());
+    if !value.is_str() && check_value(key, &value).is_err() && check_value(key, &string).is_ok() {
+        string
+    } else {
+        value
+    }
+}
+
+fn lookup(values: &toml::Value, key: &str) -> Option<toml::Value> {
+    key.split('.')
+        .try_fold(values, |v, part| v.get(part))
+        .cloned()
+}
+
+fn insert(values: &mut toml::Value, key: &str, value: toml::Value) -> Result<()> {
+    let mut parts: Vec<&str> = key.split('.').collect();
+    let last = parts.pop().ok_or_else(|| anyhow!("empty config key"))?;
+    let mut table = values
+        .as_table_mut()
+        .ok_or_else(|| anyhow!("configuration is not a table"))?;
+    for part in parts {
+        table = table
+            .entry(part)
+            .or_insert_with(|| toml::Value::Table(toml::value::Table::new()))
+            .as_table_mut()
+            .ok_or_else(|| anyhow!("{} is not a table", part))?;
+    }
+    table.insert(last.to_string(), value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_var() {
+        assert_eq!(env_var("storage_folder"), "PASSAGE_STORAGE_FOLDER");
+        assert_eq!(env_var("hooks.enabled"), "PASSAGE_HOOKS_ENABLED");
+    }
+
+    #[test]
+    fn test_parse_value() {
+        assert_eq!(parse_value("12"), toml::Value::Integer(12));
+        assert_eq!(parse_value("false"), toml::Value::Boolean(false));
+        assert_eq!(parse_value("screen"), toml::Value::String("screen".into()));
+        assert_eq!(
+            parse_value("/tmp/my vault"),
+            toml::Value::String("/tmp/my vault".into())
+        );
+    }
+
+    #[test]
+    fn test_typed_value() {
+        assert_eq!(typed_value("clip_time", "12"), toml::Value::Integer(12));
+        assert_eq!(typed_value("keyring", "false"), toml::Value::Boolean(false));
+        assert_eq!(
+            typed_value("storage_folder", "123"),
+            toml::Value::String("123".into())
+        );
+        assert_eq!(
+            typed_value("webdav.username", "true"),
+            toml::Value::String("true".into())
+        );
+        assert_eq!(
+            typed_value("clip_time", "soon"),
+            toml::Value::String("soon".into())
+        );
+    }
+
+    #[test]
+    fn test_insert_nested() {
+        let mut values = toml::Value::Table(toml::value::Table::new());
+        insert(&mut values, "hooks.enabled", toml::Value::Boolean(false)).unwrap();
+        let config: Config = values.try_into().unwrap();
+        assert!(!config.hooks.enabled);
+        assert_eq!(config.clip_time, 10);
+    }
+
+    #[test]
+    fn test_use_vault() {
+        let mut config: Config = toml::from_str(
+            "storage_folder = '/default'\nvault = 'work'\n[vaults.work]\nstorage_folder = '/work'\n\
+             [vaults.home]\nstorage_folder = '/home'\n",
+        )
+        .unwrap();
+        config.use_vault(None).unwrap();
+        assert_eq!(config.vault_folder(), Some("/work"));
+        config.use_vault(Some("home".to_string())).unwrap();
+        assert_eq!(config.vault.as_deref(), Some("home"));
+        assert_eq!(config.vault_folder(), Some("/home"));
+        assert_eq!(config.storage_folder.as_deref(), Some("/default"));
+        assert!(config.use_vault(Some("nope".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_vault_backend() {
+        let mut config: Config = toml::from_str(
+            "[s3]\nbucket = 'personal'\n[vaults.team]\nstorage_folder = '/team'\nbackend = 's3'\n\
+             [vaults.team.s3]\nbucket = 'team'\nendpoint = 'http://localhost:9000'\n",
+        )
+        .unwrap();
+        assert_eq!(config.storage_backend(), BackendKind::File);
+        assert_eq!(config.s3_config().bucket.as_deref(), Some("personal"));
+        config.use_vault(Some("team".to_string())).unwrap();
+        assert_eq!(config.storage_backend(), BackendKind::S3);
+        assert_eq!(config.s3_config().bucket.as_deref(), Some("team"));
+        assert_eq!(config.s3_config().region, "us-east-1");
+
+        let config: Config = toml::from_str(
+            "backend = 'webdav'\n[webdav]\nurl = 'https://cloud.example.com/dav/entries.toml.age'\n",
+        )
+        .unwrap();
+        assert_eq!(config.storage_backend(), BackendKind::WebDav);
+        assert_eq!(
+            config.webdav_config().url.as_deref(),
+            Some("https://cloud.example.com/dav/entries.toml.age")
+        );
+    }
+}
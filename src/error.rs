@@ -0,0 +1,105 @@
+use std::io;
+
+/// Errors of the `passage` library, anything without its own variant ends up in `Other`.
+///
+/// The command line exits with the `exit_code` of the error, these codes don't change
+/// between releases.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("storage not initialized, run `passage init`")]
+    NotInitialized,
+    #[error("storage already initialized")]
+    AlreadyInitialized,
+    #[error("wrong passphrase")]
+    WrongPassphrase,
+    #[error("the vault is locked")]
+    Locked,
+    #[error("entry not found: {0}")]
+    EntryNotFound(String),
+    #[error("entry already exists: {0}")]
+    EntryExists(String),
+    #[error("unknown vault: {0}")]
+    UnknownVault(String),
+    /// A hook script failed and its hook is configured to abort the command
+    #[error("{0}")]
+    HookFailed(String),
+    #[error("the storage was changed by someone else since it was read, try again")]
+    Conflict,
+    #[error("the storage is locked by another process ({0})")]
+    StorageLocked(String),
+    /// Keyring errors aren't `Sync`, so only their message is kept
+    #[error("failed to access the keyring: {0}")]
+    Keyring(String),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+impl Error {
+    /// Returns the exit code of the `passage` command for this error, 1 for errors without
+    /// a code of their own
+    #[must_use]
+    pub fn exit_code(&self) -> i32 {
+        match self.root() {
+            Self::NotInitialized => 3,
+            Self::AlreadyInitialized => 4,
+            Self::WrongPassphrase => 5,
+            Self::EntryNotFound(_) => 6,
+            Self::EntryExists(_) => 7,
+            Self::HookFailed(_) => 8,
+            Self::Conflict => 9,
+            Self::StorageLocked(_) => 10,
+            Self::Locked => 11,
+            Self::Keyring(_) => 12,
+            Self::UnknownVault(_) => 13,
+            Self::Io(_) | Self::Other(_) => 1,
+        }
+    }
+
+    /// Returns a name for the kind of error which stays the same between releases, like
+    /// `entry_not_found`
+    #[must_use]
+    pub fn kind(&self) -> &'static str {
+        match self.root() {
+            Self::NotInitialized => "not_initialized",
+            Self::AlreadyInitialized => "already_initialized",
+            Self::WrongPassphrase => "wrong_passphrase",
+            Self::EntryNotFound(_) => "entry_not_found",
+            Self::EntryExists(_) => "entry_exists",
+            Self::HookFailed(_) => "hook_failed",
+            Self::Conflict => "conflict",
+            Self::StorageLocked(_) => "storage_locked",
+            Self::Locked => "locked",
+            Self::Keyring(_) => "keyring",
+            Self::UnknownVault(_) => "unknown_vault",
+            Self::Io(_) => "io",
+            Self::Other(_) => "other",
+        }
+    }
+
+    /// Looks through `Other` for errors of this library which went through `anyhow`
+    fn root(&self) -> &Self {
+        match self {
+            Self::Other(error) => error.downcast_ref::<Self>().map_or(self, Self::root),
+            _ => self,
+        }
+    }
+}
+
+impl From<age::DecryptError> for Error {
+    fn from(error: age::DecryptError) -> Self {
+        match error {
+            age::DecryptError::DecryptionFailed => Self::WrongPassphrase,
+            error => Self::Other(error.into()),
+        }
+    }
+}
+
+impl From<::keyring::KeyringError> for Error {
+    fn from(error: ::keyring::KeyringError) -> Self {
+        Self::Keyring(error.to_string())
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -0,0 +1,385 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{anyhow, Result};
+use passage::config::Config;
+use passage::hooks::{run_hook, Hook, HookEvent};
+use passage::merge::{self, Side};
+use passage::{storage_dir, Secret, Storage, Vault, STORAGE_FILE};
+use serde::Serialize;
+
+use crate::{prompt_passphrase, unlock};
+
+/// The branch the current branch is tracking
+#[allow(clippy::literal_string_with_formatting_args)]
+const UPSTREAM: &str = "@{upstream}";
+
+/// A commit which changed the storage file
+#[derive(Debug, Serialize)]
+pub struct Commit {
+    pub id: String,
+    pub date: String,
+    pub message: String,
+}
+
+/// Runs git inside the storage folder and returns what it printed to stdout
+fn git_output(config: &Config, args: &[&str]) -> Result<Vec<u8>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(storage_dir(config)?)
+        .args(args)
+        .output()
+        .map_err(|e| anyhow!("failed to run git: {}", e))?;
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        Err(anyhow!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+fn git(config: &Config, args: &[&str]) -> Result<String> {
+    Ok(String::from_utf8(git_output(config, args)?)?
+        .trim_end()
+        .to_string())
+}
+
+/// Returns the storage file as of `revision`, `None` if it didn't exist then
+fn encrypted_at(config: &Config, revision: &str) -> Result<Option<Vec<u8>>> {
+    let object = format!("{}:{}", revision, STORAGE_FILE);
+    if git(config, &["cat-file", "-e", &object]).is_err() {
+        return Ok(None);
+    }
+    git_output(config, &["show", &object]).map(Some)
+}
+
+/// Decrypts the storage file as of `revision`, it has no entries if it didn't exist then
+fn storage_at(config: &Config, revision: &str, vault: &Vault) -> Result<Storage> {
+    encrypted_at(config, revision)?.map_or_else(
+        || Ok(Storage::default()),
+        |encrypted| Ok(vault.decrypt(&encrypted)?),
+    )
+}
+
+fn is_wrong_passphrase(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<passage::Error>(),
+        Some(passage::Error::WrongPassphrase)
+    )
+}
+
+/// Decrypts the storage file as of each of `revisions` for a merge. The storage might have
+/// been rekeyed on one side only, revisions the current passphrase can't decrypt are
+/// decrypted with the other passphrase, which gets asked for once.
+fn storages_at(config: &Config, revisions: &[&str], vault: &Vault) -> Result<Vec<Storage>> {
+    let mut other: Option<Secret<String>> = None;
+    let mut storages = vec![];
+    for revision in revisions {
+        match storage_at(config, revision, vault) {
+            Err(e) if is_wrong_passphrase(&e) => {
+                let passphrase = if let Some(passphrase) = &other {
+                    passphrase
+                } else {
+                    eprintln!(
+                        "The storage was rekeyed on one side only, {} uses a different \
+                         passphrase",
+                        revision
+                    );
+                    other.insert(prompt_passphrase("Enter the other passphrase: ")?)
+                };
+                let encrypted = encrypted_at(config, revision)?.unwrap_or_default();
+                storages.push(Storage::decrypt(&encrypted, passphrase)?);
+            }
+            storage => storages.push(storage?),
+        }
+    }
+    Ok(storages)
+}
+
+/// Describes how `new` differs from `old`, e.g. "Add github; update aws"
+fn describe(old: &Storage, new: &Storage) -> String {
+    let mut added: Vec<&str> = vec![];
+    let mut updated: Vec<&str> = vec![];
+    for (name, entry) in &new.entries {
+        match old.entries.get(name) {
+            None => added.push(name),
+            Some(old) if old != entry => updated.push(name),
+            Some(_) => {}
+        }
+    }
+    let mut removed: Vec<&str> = old
+        .entries
+        .keys()
+        .filter(|name| !new.entries.contains_key(*name))
+        .map(String::as_str)
+        .collect();
+
+    let mut parts = vec![];
+    for (verb, names) in &mut [
+        ("add", &mut added),
+        ("update", &mut updated),
+        ("remove", &mut removed),
+    ] {
+        if !names.is_empty() {
+            names.sort_unstable();
+            parts.push(format!("{} {}", verb, names.join(", ")));
+        }
+    }
+
+    let message = parts.join("; ");
+    let mut chars = message.chars();
+    chars.next().map_or_else(
+        || "Re-encrypt storage".to_string(),
+        |first| first.to_uppercase().chain(chars).collect(),
+    )
+}
+
+/// Turns the storage folder into a git repository and commits the storage file, git is
+/// set up to merge it with `passage merge`
+pub fn init(config: &Config, remote: Option<&str>) -> Result<()> {
+    let dir = storage_dir(config)?;
+    if Path::new(&dir).join(".git").exists() {
+        return Err(anyhow!("the storage folder is already a git repository"));
+    }
+    git(config, &["init", "--quiet"])?;
+    if let Some(remote) = remote {
+        git(config, &["remote", "add", "origin", remote])?;
+    }
+    fs::write(
+        Path::new(&dir).join(".gitattributes"),
+        format!("{} merge=passage\n", STORAGE_FILE),
+    )?;
+    git(
+        config,
+        &["config", "merge.passage.name", "passage entry merge"],
+    )?;
+    git(
+        config,
+        &["config", "merge.passage.driver", &merge_driver(config)?],
+    )?;
+    git(config, &["add", ".gitattributes"])?;
+    if Path::new(&dir).join(STORAGE_FILE).exists() {
+        git(config, &["add", STORAGE_FILE])?;
+    }
+    git(
+        config,
+        &["commit", "--quiet", "-m", "Initialize password store"],
+    )?;
+    println!("Initialized git repository in {}", dir);
+    Ok(())
+}
+
+/// Returns the command git runs to merge the storage file. It names the vault or storage
+/// folder, so `passage merge` gets the passphrase of this storage from the keyring.
+fn merge_driver(config: &Config) -> Result<String> {
+    Ok(match &config.vault {
+        Some(vault) => format!("passage --vault {} merge %O %A %B", vault),
+        None => format!(
+            "PASSAGE_STORAGE_FOLDER='{}' passage merge %O %A %B",
+            storage_dir(config)?.replace('\'', "'\\''")
+        ),
+    })
+}
+
+/// Commits local changes of the storage file, returns whether there were any
+fn commit(config: &Config, vault: &Vault) -> Result<bool> {
+    if git(config, &["status", "--porcelain", "--", STORAGE_FILE])?.is_empty() {
+        return Ok(false);
+    }
+    // after a rekey the last commit can't be decrypted anymore
+    let message = match storage_at(config, "HEAD", vault) {
+        Ok(head) => describe(&head, vault.storage()?),
+        Err(e) if is_wrong_passphrase(&e) => "Re-encrypt storage".to_string(),
+        Err(e) => return Err(e),
+    };
+    git(config, &["add", STORAGE_FILE])?;
+    git(config, &["commit", "--quiet", "-m", &message])?;
+    println!("Committed: {}", message);
+    Ok(true)
+}
+
+/// Creates a merge commit with the upstream branch, the storage file is merged entry by
+/// entry instead of by git. Conflicting entries are taken from `prefer` or prompted for.
+fn merge_upstream(
+    config: &Config,
+    vault: &mut Vault,
+    base: &str,
+    prefer: Option<Side>,
+) -> Result<()> {
+    let storages = storages_at(config, &[base, "HEAD", UPSTREAM], vault)?;
+    let merged = merge::merge(&storages[0], &storages[1], &storages[2]);
+    let storage = merge::resolve(merged, |conflict| {
+        prefer.map_or_else(|| merge::prompt(conflict), Ok)
+    })?;
+
+    // git can't merge the encrypted file and reports a conflict for it, that's expected;
+    // the merge driver set up by `passage git init` is disabled, the merge already happened
+    let _ = git(
+        config,
+        &[
+            "-c",
+            "merge.passage.driver=true",
+            "merge",
+            "--quiet",
+            "--no-commit",
+            "--no-ff",
+            UPSTREAM,
+        ],
+    );
+    let conflicts = git(config, &["diff", "--name-only", "--diff-filter=U"])?;
+    let others: Vec<&str> = conflicts
+        .lines()
+        .filter(|file| *file != STORAGE_FILE)
+        .collect();
+    if !others.is_empty() {
+        git(config, &["merge", "--abort"])?;
+        return Err(anyhow!(
+            "merge conflicts in other files: {}",
+            others.join(", ")
+        ));
+    }
+
+    *vault.storage_mut()? = storage;
+    vault.save()?;
+    git(config, &["add", STORAGE_FILE])?;
+    let upstream = git(config, &["rev-parse", "--abbrev-ref", UPSTREAM])?;
+    git(
+        config,
+        &[
+            "commit",
+            "--quiet",
+            "-m",
+            &format!("Merge entries from {}", upstream),
+        ],
+    )?;
+    println!("Merged changes from {}", upstream);
+    Ok(())
+}
+
+/// Commits local changes, brings in changes from the remote and pushes the result
+pub fn sync(config: &Config, prefer: Option<Side>) -> Result<()> {
+    run_hook(config, &Hook::PreLoad, &HookEvent::SyncStorage, &[])?;
+    let mut vault = unlock(config, "Enter passphrase: ")?;
+    run_hook(config, &Hook::PostLoad, &HookEvent::SyncStorage, &[])?;
+    commit(config, &vault)?;
+
+    let remotes = git(config, &["remote"])?;
+    let Some(remote) = remotes.lines().next() else {
+        println!("No remote configured, changes are only committed locally");
+        return Ok(());
+    };
+    git(config, &["fetch", "--quiet", remote])?;
+
+    if git(config, &["rev-parse", "--abbrev-ref", UPSTREAM]).is_err() {
+        git(
+            config,
+            &["push", "--quiet", "--set-upstream", remote, "HEAD"],
+        )?;
+        println!("Pushed to {}", remote);
+        return Ok(());
+    }
+
+    let head = git(config, &["rev-parse", "HEAD"])?;
+    let upstream = git(config, &["rev-parse", UPSTREAM])?;
+    let base = git(config, &["merge-base", "HEAD", UPSTREAM])?;
+    if head == upstream {
+        println!("Already up to date");
+        return Ok(());
+    } else if head == base {
+        git(config, &["merge", "--quiet", "--ff-only", UPSTREAM])?;
+        println!("Pulled changes from {}", remote);
+        return Ok(());
+    } else if upstream != base {
+        merge_upstream(config, &mut vault, &base, prefer)?;
+    }
+    git(config, &["push", "--quiet"])?;
+    println!("Pushed to {}", remote);
+    Ok(())
+}
+
+/// Returns the commits which changed the storage file, newest first
+pub fn log(config: &Config) -> Result<Vec<Commit>> {
+    let output = git(
+        config,
+        &[
+            "log",
+            "--date=short",
+            "--format=%h%x09%ad%x09%s",
+            "--",
+            STORAGE_FILE,
+        ],
+    )?;
+    Ok(output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, '\t');
+            Some(Commit {
+                id: fields.next()?.to_string(),
+                date: fields.next()?.to_string(),
+                message: fields.next()?.to_string(),
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use passage::Entry;
+
+    use super::*;
+
+    /// Creates a storage of entries with just a password, from pairs of name and password
+    fn storage(entries: &[(&str, &str)]) -> Storage {
+        Storage {
+            entries: entries
+                .iter()
+                .map(|(name, password)| ((*name).to_string(), Entry::new(*password)))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_describe() {
+        let old = storage(&[("aws", "1"), ("old", "1"), ("same", "1")]);
+        let new = storage(&[
+            ("aws", "2"),
+            ("github", "1"),
+            ("gitlab", "1"),
+            ("same", "1"),
+        ]);
+        assert_eq!(
+            describe(&old, &new),
+            "Add github, gitlab; update aws; remove old"
+        );
+        assert_eq!(describe(&new, &new), "Re-encrypt storage");
+        assert_eq!(
+            describe(&new, &storage(&[])),
+            "Remove aws, github, gitlab, same"
+        );
+    }
+
+    #[test]
+    fn test_merge_driver() {
+        let config = Config {
+            storage_folder: Some("/home/me/it's mine".to_string()),
+            ..Config::default()
+        };
+        assert_eq!(
+            merge_driver(&config).unwrap(),
+            "PASSAGE_STORAGE_FOLDER='/home/me/it'\\''s mine' passage merge %O %A %B"
+        );
+
+        let config = Config {
+            vault: Some("team".to_string()),
+            ..config
+        };
+        assert_eq!(
+            merge_driver(&config).unwrap(),
+            "passage --vault team merge %O %A %B"
+        );
+    }
+}
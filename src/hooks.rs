@@ -0,0 +1,369 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+
+use crate::config::{Config, HookConfig, HooksConfig, OnFailure};
+use crate::{entries_file, hooks_dir, storage_dir, Error};
+
+/// How long the output of a hook is still read after it exited
+const OUTPUT_GRACE: Duration = Duration::from_secs(1);
+
+/// Set when stdout carries a JSON document, hooks print to stderr then
+static OUTPUT_ON_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Makes hooks print their output (and which hook runs) to stderr instead of stdout, for
+/// when stdout is reserved for machine readable output
+pub fn print_to_stderr(on: bool) {
+    OUTPUT_ON_STDERR.store(on, Ordering::Relaxed);
+}
+
+/// Prints a line of hook output to stdout, or stderr if stdout is reserved
+fn print_line(line: &str) {
+    if OUTPUT_ON_STDERR.load(Ordering::Relaxed) {
+        eprintln!("{}", line);
+    } else {
+        println!("{}", line);
+    }
+}
+
+/// Represents callable scripts which can be triggered at certain times
+pub enum Hook {
+    PreLoad,
+    PostLoad,
+    PreSave,
+    PostSave,
+}
+
+impl Hook {
+    pub const ALL: [Self; 4] = [Self::PreLoad, Self::PostLoad, Self::PreSave, Self::PostSave];
+
+    #[must_use]
+    pub fn name(&self) -> String {
+        match *self {
+            Self::PreLoad => "pre_load".to_string(),
+            Self::PostLoad => "post_load".to_string(),
+            Self::PreSave => "pre_save".to_string(),
+            Self::PostSave => "post_save".to_string(),
+        }
+    }
+
+    const fn config<'a>(&self, config: &'a HooksConfig) -> &'a HookConfig {
+        match *self {
+            Self::PreLoad => &config.pre_load,
+            Self::PostLoad => &config.post_load,
+            Self::PreSave => &config.pre_save,
+            Self::PostSave => &config.post_save,
+        }
+    }
+}
+
+/// Represents events which can trigger hooks
+#[derive(Debug)]
+pub enum HookEvent {
+    NewEntry,
+    ListEntries,
+    ShowEntry,
+    EditEntry,
+    RemoveEntry,
+    TypeEntry,
+    MoveEntry,
+    InitStorage,
+    RekeyStorage,
+    ImportEntries,
+    FindEntries,
+    CopyEntry,
+    BrowseEntries,
+    SyncStorage,
+    MergeStorage,
+}
+
+impl HookEvent {
+    #[must_use]
+    pub fn name(&self) -> String {
+        match *self {
+            Self::NewEntry => "new_entry".to_string(),
+            Self::ListEntries => "list_entries".to_string(),
+            Self::ShowEntry => "show_entry".to_string(),
+            Self::EditEntry => "edit_entry".to_string(),
+            Self::RemoveEntry => "remove_entry".to_string(),
+            Self::TypeEntry => "type_entry".to_string(),
+            Self::MoveEntry => "move_entry".to_string(),
+            Self::InitStorage => "init_storage".to_string(),
+            Self::RekeyStorage => "rekey_storage".to_string(),
+            Self::ImportEntries => "import_entries".to_string(),
+            Self::FindEntries => "find_entries".to_string(),
+            Self::CopyEntry => "copy_entry".to_string(),
+            Self::BrowseEntries => "browse_entries".to_string(),
+            Self::SyncStorage => "sync_storage".to_string(),
+            Self::MergeStorage => "merge_storage".to_string(),
+        }
+    }
+
+    /// Returns the name of the subcommand which triggers this event
+    const fn command(&self) -> &'static str {
+        match *self {
+            Self::NewEntry => "new",
+            Self::ListEntries => "list",
+            Self::ShowEntry => "show",
+            Self::EditEntry => "edit",
+            Self::RemoveEntry => "remove",
+            Self::TypeEntry => "type",
+            Self::MoveEntry => "mv",
+            Self::InitStorage => "init",
+            Self::RekeyStorage => "rekey",
+            Self::ImportEntries => "import",
+            Self::FindEntries => "find",
+            Self::CopyEntry => "cp",
+            Self::BrowseEntries => "tui",
+            Self::SyncStorage => "git sync",
+            Self::MergeStorage => "merge",
+        }
+    }
+}
+
+/// Describes what triggered a hook, hooks get this as JSON on stdin and as `PASSAGE_*`
+/// environment variables. Must never contain any secrets.
+#[derive(Debug, Serialize)]
+struct HookContext<'a> {
+    hook: String,
+    event: String,
+    command: &'static str,
+    entries: &'a [&'a str],
+    storage_file: String,
+    version: &'static str,
+}
+
+impl HookContext<'_> {
+    fn env(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("PASSAGE_HOOK", self.hook.clone()),
+            ("PASSAGE_EVENT", self.event.clone()),
+            ("PASSAGE_COMMAND", self.command.to_string()),
+            ("PASSAGE_ENTRIES", self.entries.join("\n")),
+            ("PASSAGE_STORAGE_FILE", self.storage_file.clone()),
+            ("PASSAGE_VERSION", self.version.to_string()),
+        ]
+    }
+}
+
+/// A script which runs for a hook, either `hooks/<hook>` or a file in `hooks/<hook>.d`
+pub struct Script {
+    pub path: PathBuf,
+    /// Path relative to the hooks directory, e.g. `post_save.d/10-git`
+    pub name: String,
+    pub executable: bool,
+}
+
+impl Script {
+    fn new(path: PathBuf, name: String) -> Result<Self> {
+        let executable = is_executable(&fs::metadata(&path)?);
+        Ok(Self {
+            path,
+            name,
+            executable,
+        })
+    }
+}
+
+/// Returns the scripts for `hook` in the order they run: the script named after the hook,
+/// followed by the files in `<hook>.d` in lexical order. Hidden files are left out.
+pub fn scripts(config: &Config, hook: &Hook) -> Result<Vec<Script>> {
+    let dir = PathBuf::from(hooks_dir(config)?);
+    let mut scripts = vec![];
+
+    let path = dir.join(hook.name());
+    if path.is_file() {
+        scripts.push(Script::new(path, hook.name())?);
+    }
+
+    let subdir = format!("{}.d", hook.name());
+    if let Ok(read_dir) = fs::read_dir(dir.join(&subdir)) {
+        let mut names = read_dir
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        names.sort();
+        for name in names {
+            let path = dir.join(&subdir).join(&name);
+            let name = name.to_string_lossy();
+            if path.is_file() && !name.starts_with('.') {
+                scripts.push(Script::new(path, format!("{}/{}", subdir, name))?);
+            }
+        }
+    }
+
+    Ok(scripts)
+}
+
+/// Runs the scripts for `hook`.
+///
+/// A failing script aborts the current command (and skips the remaining scripts) unless
+/// the hook is configured to only warn. `entries` are the names of the entries the
+/// command works on (if known at this point).
+pub fn run_hook(config: &Config, hook: &Hook, event: &HookEvent, entries: &[&str]) -> Result<()> {
+    if !config.hooks.enabled {
+        return Ok(());
+    }
+    let scripts = scripts(config, hook)?;
+    if scripts.is_empty() {
+        return Ok(());
+    }
+
+    let context = HookContext {
+        hook: hook.name(),
+        event: event.name(),
+        command: event.command(),
+        entries,
+        storage_file: entries_file(config)?,
+        version: env!("CARGO_PKG_VERSION"),
+    };
+    let timeout = Duration::from_secs(
+        hook.config(&config.hooks)
+            .timeout
+            .unwrap_or(config.hooks.timeout),
+    );
+    for script in scripts {
+        if !script.executable {
+            eprintln!(
+                "Warning: skipping {} hook, it isn't executable",
+                script.name
+            );
+            continue;
+        }
+        if let Some(failure) = run_script(config, &script, &context, timeout)? {
+            match hook.config(&config.hooks).on_failure {
+                OnFailure::Fatal => return Err(Error::HookFailed(failure).into()),
+                OnFailure::Warn => eprintln!("Warning: {}", failure),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs a single hook script, returns a description of what went wrong if it failed
+fn run_script(
+    config: &Config,
+    script: &Script,
+    context: &HookContext,
+    timeout: Duration,
+) -> Result<Option<String>> {
+    print_line(&format!("Running {} hook", script.name));
+    let mut command = Command::new(&script.path);
+    command
+        .arg(&context.event)
+        .envs(context.env())
+        .current_dir(storage_dir(config)?)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    // run the hook in its own process group, so it can be killed along with everything
+    // it started (like a `git push` waiting for credentials)
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    let mut child = command.spawn()?;
+
+    let json = serde_json::to_vec(context)?;
+    let mut stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| anyhow!("failed to open stdin of {} hook", script.name))?;
+    // each thread holds on to `done`, so `finished` disconnects once all of them are done
+    let (done, finished) = mpsc::channel::<()>();
+    // written from a thread, so a hook which doesn't read stdin can't block us;
+    // hooks are free to ignore it, so a broken pipe is fine
+    let writer_done = done.clone();
+    thread::spawn(move || {
+        let _ = stdin.write_all(&json);
+        drop(writer_done);
+    });
+    stream_output(script.name.clone(), child.stdout.take(), done.clone());
+    stream_output(script.name.clone(), child.stderr.take(), done);
+
+    let status = wait_timeout(&mut child, timeout)?;
+    // processes the hook left running in the background can keep its pipes open, the
+    // threads are left behind if they don't finish in time
+    let _ = finished.recv_timeout(OUTPUT_GRACE);
+
+    Ok(match status {
+        Some(status) if status.success() => None,
+        Some(status) => Some(format!("{} hook failed ({})", script.name, status)),
+        None => Some(format!(
+            "{} hook timed out after {} seconds",
+            script.name,
+            timeout.as_secs()
+        )),
+    })
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+const fn is_executable(_metadata: &fs::Metadata) -> bool {
+    true
+}
+
+/// Prints every line of `output` prefixed with the hook name as soon as it arrives, `done`
+/// gets dropped at the end of the output
+fn stream_output<R: Read + Send + 'static>(name: String, output: Option<R>, done: Sender<()>) {
+    thread::spawn(move || {
+        if let Some(output) = output {
+            for line in BufReader::new(output).split(b'\n').map_while(Result::ok) {
+                print_line(&format!(
+                    "{}: {}",
+                    name,
+                    String::from_utf8_lossy(&line).trim_end_matches('\r')
+                ));
+            }
+        }
+        drop(done);
+    });
+}
+
+/// Waits for `child` to exit, returns `None` if it got killed because it took longer
+/// than `timeout`
+fn wait_timeout(child: &mut Child, timeout: Duration) -> Result<Option<ExitStatus>> {
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if start.elapsed() >= timeout {
+            kill(child)?;
+            child.wait()?;
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[cfg(unix)]
+fn kill(child: &Child) -> Result<()> {
+    use nix::sys::signal::{killpg, Signal};
+    use nix::unistd::Pid;
+    use std::convert::TryFrom;
+
+    let pid = i32::try_from(child.id())?;
+    match killpg(Pid::from_raw(pid), Signal::SIGKILL) {
+        // the whole group exited in the meantime
+        Ok(()) | Err(nix::errno::Errno::ESRCH) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(not(unix))]
+fn kill(child: &mut Child) -> Result<()> {
+    Ok(child.kill()?)
+}
@@ -0,0 +1,80 @@
+use ::keyring::{Keyring, KeyringError};
+use secrecy::{ExposeSecret, Secret};
+
+use crate::config::Config;
+use crate::{vault, Result};
+
+const KEYRING_APP_NAME: &str = "passage";
+
+/// Returns the name the passphrase of the storage folder with the identifier `id` is stored
+/// under in the keyring
+fn user(id: &str) -> String {
+    format!("{}@{}", whoami::username(), id)
+}
+
+/// Returns the passphrase stored in the keyring, if the keyring integration is enabled and
+/// holds one. A keyring which can't be read only gets a warning, the passphrase can still
+/// be typed in.
+pub fn passphrase(config: &Config) -> Result<Option<Secret<String>>> {
+    if !config.keyring {
+        return Ok(None);
+    }
+    // without an identifier nothing can have been stored yet
+    let Some(id) = vault::read_id(config)? else {
+        return Ok(None);
+    };
+    match Keyring::new(KEYRING_APP_NAME, &user(&id)).get_password() {
+        Ok(passphrase) => Ok(Some(Secret::new(passphrase))),
+        Err(KeyringError::NoPasswordFound) => Ok(None),
+        Err(e) => {
+            eprintln!("Warning: {}", crate::Error::from(e));
+            Ok(None)
+        }
+    }
+}
+
+/// Stores `passphrase` in the keyring if the keyring integration is enabled, this gives the
+/// storage folder an identifier if it has none yet
+pub fn store(config: &Config, passphrase: &Secret<String>) -> Result<()> {
+    if !config.keyring {
+        return Ok(());
+    }
+    let username = user(&vault::id(config)?);
+    Keyring::new(KEYRING_APP_NAME, &username).set_password(passphrase.expose_secret())?;
+    Ok(())
+}
+
+/// Returns whether the keyring holds a passphrase for the storage folder of `config`
+pub fn is_stored(config: &Config) -> Result<bool> {
+    let Some(id) = vault::read_id(config)? else {
+        return Ok(false);
+    };
+    match Keyring::new(KEYRING_APP_NAME, &user(&id)).get_password() {
+        Ok(_) => Ok(true),
+        Err(KeyringError::NoPasswordFound) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Deletes the passphrase of the storage folder of `config` from the keyring, returns
+/// whether there was one
+pub fn forget(config: &Config) -> Result<bool> {
+    let Some(id) = vault::read_id(config)? else {
+        return Ok(false);
+    };
+    delete(&user(&id))
+}
+
+/// Deletes the passphrase stored by versions which didn't tell storage folders apart,
+/// returns whether there was one
+pub fn forget_legacy() -> Result<bool> {
+    delete(&whoami::username())
+}
+
+fn delete(username: &str) -> Result<bool> {
+    match Keyring::new(KEYRING_APP_NAME, username).delete_password() {
+        Ok(()) => Ok(true),
+        Err(KeyringError::NoPasswordFound) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,98 @@
+//! The library behind the `passage` password manager, for reading and changing password
+//! stores without going through the command line.
+//!
+//! ```no_run
+//! use passage::{Config, Secret, Vault};
+//!
+//! let mut vault = Vault::open(&Config::load()?)?;
+//! vault.unlock(Secret::new("passphrase".to_string()))?;
+//! for name in vault.list()? {
+//!     println!("{}", name);
+//! }
+//! # Ok::<(), passage::Error>(())
+//! ```
+#![forbid(unsafe_code)]
+#![deny(clippy::cargo)]
+#![deny(clippy::pedantic)]
+#![deny(clippy::nursery)]
+#![deny(clippy::panic)]
+#![allow(clippy::multiple_crate_versions)]
+#![allow(clippy::missing_errors_doc)]
+
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use anyhow::anyhow;
+use directories_next::ProjectDirs;
+
+pub mod backend;
+pub mod config;
+mod error;
+pub mod hooks;
+pub mod keyring;
+pub mod merge;
+mod storage;
+mod vault;
+
+pub use config::Config;
+pub use error::{Error, Result};
+pub use secrecy::Secret;
+pub use storage::{Entry, Storage};
+pub use vault::Vault;
+
+/// Name of the encrypted file holding the entries inside the storage folder
+pub const STORAGE_FILE: &str = "entries.toml.age";
+
+/// Returns the path to the storage folder containing the `entries_file`, which is the
+/// folder of the vault in use if there is one
+pub fn storage_dir(config: &Config) -> Result<String> {
+    config
+        .vault_folder()
+        .or(config.storage_folder.as_deref())
+        .map_or_else(
+            || {
+                ProjectDirs::from("", "", "passage")
+                    .map(|pd| pd.data_dir().display().to_string())
+                    .ok_or_else(|| anyhow!("couldn't determine project storage folder").into())
+            },
+            |f| Ok(f.to_string()),
+        )
+}
+
+/// Returns the path to the entries.toml.age file
+pub fn entries_file(config: &Config) -> Result<String> {
+    Ok(Path::new(&storage_dir(config)?)
+        .join(STORAGE_FILE)
+        .display()
+        .to_string())
+}
+
+/// Runs `command` (split on whitespace, without a shell) and returns what it printed
+/// without the trailing newline, `what` names the secret in error messages
+pub fn secret_from_command(command: &str, what: &str) -> Result<Secret<String>> {
+    let mut words = command.split_whitespace();
+    let program = words
+        .next()
+        .ok_or_else(|| anyhow!("the command printing the {} is empty", what))?;
+    let output = Command::new(program)
+        .args(words)
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| anyhow!("failed to run {}: {}", program, e))?;
+    if !output.status.success() {
+        return Err(anyhow!("{} failed to print the {}", program, what).into());
+    }
+    let secret = String::from_utf8(output.stdout)
+        .map_err(|_| anyhow!("the {} printed by {} isn't UTF-8", what, program))?;
+    Ok(Secret::new(
+        secret.trim_end_matches(&['\r', '\n'][..]).to_string(),
+    ))
+}
+
+/// Returns the path to the hooks directory
+pub fn hooks_dir(config: &Config) -> Result<String> {
+    Ok(Path::new(&storage_dir(config)?)
+        .join("hooks")
+        .display()
+        .to_string())
+}
@@ -6,81 +6,96 @@
 #![allow(clippy::multiple_crate_versions)]
 
 use std::fs;
-use std::fs::File;
-use std::io;
-use std::path::Path;
-use std::process::Command;
-use std::{
-    collections::HashMap,
-    io::{BufReader, Read, Write},
-};
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use anyhow::{anyhow, Error, Result};
-use clipboard::ClipboardContext;
-use clipboard::ClipboardProvider;
 use directories_next::ProjectDirs;
+use passage::config::{self, BackendKind, Config, ShowOn};
+use passage::hooks::{self, run_hook, Hook, HookEvent};
+use passage::merge::{self, Side};
+use passage::{backend, entries_file, hooks_dir, keyring, storage_dir, Entry, Storage, Vault};
 use secrecy::{ExposeSecret, Secret};
-use serde::{Deserialize, Serialize};
+use serde_json::json;
+use structopt::clap::Shell;
 use structopt::StructOpt;
 
-const KEYRING_APP_NAME: &str = "passage";
+mod autotype;
+mod clipboard;
+mod completions;
+mod git;
+mod man;
+mod tui;
 
-#[derive(Debug, Deserialize, Serialize)]
-struct Storage {
-    #[serde(flatten)]
-    entries: HashMap<String, Entry>,
-}
+#[derive(Debug, StructOpt)]
+#[structopt(name = "passage", about = "Password manager with age encryption")]
+struct Opt {
+    #[structopt(flatten)]
+    cmd: Cmd,
 
-#[derive(Debug, Deserialize, Serialize)]
-struct Entry {
-    password: String,
+    #[structopt(long, short)]
+    /// Disable the keyring integration
+    no_keyring: bool,
+
+    #[structopt(long, global = true)]
+    /// Use this vault instead of the configured one
+    vault: Option<String>,
+
+    #[structopt(long, global = true)]
+    /// Print errors as JSON to stderr
+    json: bool,
+
+    #[structopt(long, global = true, default_value = "text", possible_values = &["text", "json"])]
+    /// Print the results of list, info, show, find, git log and keyring check as JSON
+    output: Format,
 }
 
-/// Represents callable scripts which can be triggered at certain times
-enum Hook {
-    PreLoad,
-    PostSave,
+/// How commands print their results
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Text,
+    /// A JSON document on stdout, prompts go to stderr
+    Json,
 }
 
-impl Hook {
-    fn name(&self) -> String {
-        match *self {
-            Self::PreLoad => "pre_load".to_string(),
-            Self::PostSave => "post_save".to_string(),
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(anyhow!("expected text or json, got {}", s)),
         }
     }
 }
-/// Represents events which can trigger hooks
-#[derive(Debug)]
-enum HookEvent {
-    NewEntry,
-    ListEntries,
-    ShowEntry,
-    EditEntry,
-    RemoveEntry,
+
+/// What `__complete` lists
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Names {
+    Entries,
+    Vaults,
 }
 
-impl HookEvent {
-    fn name(&self) -> String {
-        match *self {
-            Self::NewEntry => "new_entry".to_string(),
-            Self::ListEntries => "list_entries".to_string(),
-            Self::ShowEntry => "show_entry".to_string(),
-            Self::EditEntry => "edit_entry".to_string(),
-            Self::RemoveEntry => "remove_entry".to_string(),
+impl FromStr for Names {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "entries" => Ok(Self::Entries),
+            "vaults" => Ok(Self::Vaults),
+            _ => Err(anyhow!("expected entries or vaults, got {}", s)),
         }
     }
 }
 
-#[derive(Debug, StructOpt)]
-#[structopt(name = "passage", about = "Password manager with age encryption")]
-struct Opt {
-    #[structopt(flatten)]
-    cmd: Cmd,
+/// Set when stdout carries JSON, prompts go to stderr then
+static PROMPT_ON_STDERR: AtomicBool = AtomicBool::new(false);
 
-    #[structopt(long, short)]
-    /// Disable the keyring integration
-    no_keyring: bool,
+fn print_json(document: &serde_json::Value) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(document)?);
+    Ok(())
 }
 
 #[derive(Debug, StructOpt)]
@@ -88,7 +103,10 @@ enum Cmd {
     /// Initialize the password store
     Init,
     /// Add a new entry
-    New,
+    New {
+        #[structopt(flatten)]
+        fields: EntryFields,
+    },
     /// List all known entries
     List,
     /// Decrypt and show an entry
@@ -98,116 +116,226 @@ enum Cmd {
         #[structopt(long, short)]
         /// Print the password instead of copying it to the clipboard
         on_screen: bool,
+
+        #[structopt(long, short, conflicts_with = "on-screen")]
+        /// Copy the password to the clipboard, even if `show_on` is set to `screen`
+        clipboard: bool,
+
+        #[structopt(long)]
+        /// Seconds until the clipboard gets cleared again
+        clip_time: Option<u64>,
+
+        #[structopt(long, short)]
+        /// Use the primary selection instead of the clipboard
+        primary: bool,
+
+        #[structopt(long)]
+        /// Include the password in JSON output
+        reveal: bool,
+    },
+    /// Edit an entry, without any options the password gets changed
+    Edit {
+        entry: String,
+
+        #[structopt(flatten)]
+        fields: EntryFields,
+    },
+    /// Type an entry into the focused window
+    Type {
+        entry: String,
+
+        #[structopt(long, short)]
+        /// Autotype sequence, e.g. "{username}{TAB}{password}{ENTER}"
+        sequence: Option<String>,
+    },
+    /// Find entries whose name or username contains a pattern
+    Find {
+        pattern: String,
+
+        #[structopt(long)]
+        /// Search all vaults instead of the one in use
+        all_vaults: bool,
     },
-    /// Edit an entry
-    Edit { entry: String },
     /// Remove an entry
     Remove { entry: String },
+    /// Rename an entry
+    Mv { from: String, to: String },
+    /// Copy an entry from one vault to another
+    Cp {
+        entry: String,
+
+        #[structopt(long)]
+        /// Vault to copy the entry from
+        from: String,
+
+        #[structopt(long)]
+        /// Vault to copy the entry to
+        to: String,
+
+        #[structopt(long)]
+        /// Replace an existing entry with the same name in the destination
+        overwrite: bool,
+    },
+    /// Change the passphrase of the password store
+    Rekey,
+    /// Import entries from an unencrypted TOML file (in the same format as the store)
+    Import {
+        file: String,
+
+        #[structopt(long)]
+        /// Replace existing entries with the same name
+        overwrite: bool,
+    },
+    /// Merge three versions of a storage file entry by entry, usable as a git merge driver
+    Merge {
+        /// The common ancestor of both versions
+        base: String,
+        /// Our version, the result gets written here unless `--out` is given
+        ours: String,
+        /// Their version
+        theirs: String,
+
+        #[structopt(long, short)]
+        /// Write the result to this file instead
+        out: Option<String>,
+
+        #[structopt(long, possible_values = &["ours", "theirs"])]
+        /// Resolve conflicts with this side instead of asking
+        prefer: Option<Side>,
+    },
     /// Display status information
     Info,
     /// Keyring related commands
     Keyring(KeyringOpt),
-}
-
-#[derive(Debug, StructOpt)]
-enum KeyringOpt {
-    /// Checks if the keyring integration works
-    Check,
-    /// Deletes the password from the keyring
-    Forget,
-}
+    /// Read and write settings in the config file
+    Config(ConfigOpt),
+    /// Synchronize the password store with a git repository
+    Git(GitOpt),
+    /// Manage named vaults, each with its own storage folder
+    Vault(VaultOpt),
+    /// Print a completion script for bash, zsh or fish
+    Completions {
+        #[structopt(possible_values = &["bash", "zsh", "fish"])]
+        shell: Shell,
+    },
+    /// Browse and edit the entries in a full-screen terminal interface
+    Tui,
+    /// Print the man page of passage or one of its subcommands
+    Man {
+        #[structopt(long)]
+        /// Write the man pages of passage and all subcommands to this folder instead
+        dir: Option<String>,
 
-/// Returns the path to the storage folder containing the `entries_file`
-fn storage_dir() -> Result<String> {
-    match std::env::var("PASSAGE_STORAGE_FOLDER") {
-        Ok(f) => Ok(f),
-        Err(_) => match ProjectDirs::from("", "", "passage") {
-            Some(pd) => {
-                let dir = pd.data_dir().display().to_string();
-                Ok(dir)
-            }
-            None => Err(anyhow!("couldn't determine project storage folder")),
-        },
-    }
+        /// The subcommand to print the man page for, like `vault add`
+        command: Vec<String>,
+    },
 }
 
-/// Returns the path to the entries.toml.age file
-fn entries_file() -> Result<String> {
-    Ok(Path::new(&storage_dir()?)
-        .join("entries.toml.age")
-        .display()
-        .to_string())
-}
+/// Arguments of the hidden `__complete` command, which prints entry or vault names for the
+/// completion scripts. It isn't part of `Cmd` because clap can't generate bash completions
+/// for subcommands whose name starts with underscores.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "__complete")]
+struct CompleteOpt {
+    #[structopt(possible_values = &["entries", "vaults"])]
+    names: Names,
 
-/// Returns the path toth the hooks directory
-fn hooks_dir() -> Result<String> {
-    Ok(Path::new(&storage_dir()?)
-        .join("hooks")
-        .display()
-        .to_string())
+    #[structopt(long)]
+    /// List the entries of this vault instead of the configured one
+    vault: Option<String>,
 }
 
-fn encrypt(plaintext: &[u8], passphrase: Secret<String>) -> Result<Vec<u8>, Error> {
-    let encryptor = age::Encryptor::with_user_passphrase(passphrase);
-
-    let mut encrypted = vec![];
-    let mut writer = encryptor.wrap_output(&mut encrypted).map_err(Error::msg)?;
-    writer.write_all(plaintext)?;
-    writer.finish()?;
+/// Fields of an entry besides the password
+#[derive(Debug, StructOpt)]
+struct EntryFields {
+    #[structopt(long, short)]
+    /// Username for this entry (pass "" to remove it)
+    username: Option<String>,
 
-    Ok(encrypted)
+    #[structopt(long)]
+    /// Autotype sequence for this entry (pass "" to remove it)
+    autotype: Option<String>,
 }
 
-fn decrypt(encrypted: &[u8], passphrase: &Secret<String>) -> Result<Vec<u8>, Error> {
-    let decryptor = match age::Decryptor::new(&encrypted[..])? {
-        age::Decryptor::Passphrase(d) => d,
-        age::Decryptor::Recipients(..) => unreachable!(),
-    };
+impl EntryFields {
+    const fn is_empty(&self) -> bool {
+        self.username.is_none() && self.autotype.is_none()
+    }
 
-    let mut decrypted = vec![];
-    let mut reader = decryptor.decrypt(passphrase, None)?;
-    loop {
-        let bytes = reader.read_to_end(&mut decrypted)?;
-        if bytes == 0 {
-            break;
+    /// Sets the given fields on `entry`, empty values remove the field
+    fn apply(self, entry: &mut Entry) {
+        let non_empty = |v: String| if v.is_empty() { None } else { Some(v) };
+        if let Some(username) = self.username {
+            entry.username = non_empty(username);
+        }
+        if let Some(autotype) = self.autotype {
+            entry.autotype = non_empty(autotype);
         }
     }
+}
 
-    Ok(decrypted)
+#[derive(Debug, StructOpt)]
+enum KeyringOpt {
+    /// Checks if the keyring integration works
+    Check {
+        #[structopt(long)]
+        /// Check the default storage folder and every vault
+        all: bool,
+    },
+    /// Deletes the password from the keyring
+    Forget {
+        #[structopt(long)]
+        /// Forget the passphrases of the default storage folder and every vault
+        all: bool,
+    },
 }
 
-fn load_entries(passphrase: &Secret<String>) -> Result<Storage> {
-    let mut encrypted: Vec<u8> = vec![];
-    let file = match fs::metadata(entries_file()?) {
-        Ok(_) => File::open(entries_file()?)?,
-        Err(_) => return Err(anyhow!("storage not initialized, run `passage init`")),
-    };
-    let mut buf = BufReader::new(file);
-    buf.read_to_end(&mut encrypted)?;
-    if let 0 = encrypted.len() {
-        Ok(Storage {
-            entries: HashMap::new(),
-        })
-    } else {
-        let decrypted = decrypt(&encrypted, passphrase)?;
-        let decrypted = String::from_utf8(decrypted)?;
-        let decrypted: Storage = toml::from_str(&decrypted)?;
-        Ok(decrypted)
-    }
+#[derive(Debug, StructOpt)]
+enum ConfigOpt {
+    /// Print the value of a setting
+    Get { key: String },
+    /// Change a setting
+    Set { key: String, value: String },
+    /// Print all settings
+    List,
 }
 
-fn save_entries(passphrase: Secret<String>, storage: &Storage) -> Result<()> {
-    let bytes: Vec<u8> = toml::to_vec(&storage)?;
-    let encrypted = encrypt(&bytes, passphrase)?;
-    let mut file = File::create(entries_file()?)?;
-    file.write_all(&encrypted)?;
-    Ok(())
+#[derive(Debug, StructOpt)]
+enum GitOpt {
+    /// Turn the storage folder into a git repository
+    Init {
+        /// URL of the remote repository to sync with
+        remote: Option<String>,
+    },
+    /// Commit changes, pull, merge and push
+    Sync {
+        #[structopt(long, possible_values = &["ours", "theirs"])]
+        /// Resolve conflicts with this side instead of asking
+        prefer: Option<Side>,
+    },
+    /// Show the commits which changed the password store
+    Log,
+}
+
+#[derive(Debug, StructOpt)]
+enum VaultOpt {
+    /// Register a vault, its storage folder defaults to one next to the default storage
+    Add {
+        name: String,
+        storage_folder: Option<String>,
+    },
+    /// List all vaults, the one in use is marked with *
+    List,
+    /// Switch to a vault, without a name the default storage folder is used again
+    Use { name: Option<String> },
+    /// Forget a vault, its storage folder is left alone
+    Remove { name: String },
 }
 
-fn new_entry(no_keyring: bool) -> Result<(), Error> {
-    run_hook(&Hook::PreLoad, &HookEvent::NewEntry)?;
-    let passphrase = get_passphrase("Passphrase: ", no_keyring)?;
-    let mut storage = load_entries(&passphrase)?;
+fn new_entry(config: &Config, fields: EntryFields) -> Result<(), Error> {
+    run_hook(config, &Hook::PreLoad, &HookEvent::NewEntry, &[])?;
+    let mut vault = unlock(config, "Passphrase: ")?;
+    run_hook(config, &Hook::PostLoad, &HookEvent::NewEntry, &[])?;
 
     print!("New entry: ");
     io::stdout().flush()?;
@@ -215,15 +343,10 @@ fn new_entry(no_keyring: bool) -> Result<(), Error> {
     io::stdin().read_line(&mut entry)?;
     let entry = entry.trim();
 
-    if storage.entries.contains_key(entry) {
-        print!("'{}' already exists. Overwrite (y/N)? ", entry);
-        io::stdout().flush()?;
-        let mut overwrite = String::new();
-        io::stdin().read_line(&mut overwrite)?;
-        let overwrite = overwrite.trim();
-        if overwrite.to_uppercase() != "Y" {
-            return Ok(());
-        }
+    if vault.contains(entry)?
+        && !confirm(&format!("'{}' already exists. Overwrite (y/N)? ", entry))?
+    {
+        return Ok(());
     }
 
     let password = Secret::new(rpassword::prompt_password_stdout(&format!(
@@ -231,254 +354,869 @@ fn new_entry(no_keyring: bool) -> Result<(), Error> {
         entry
     ))?);
 
-    storage.entries.insert(
-        entry.to_owned(),
-        Entry {
-            password: password.expose_secret().to_string(),
-        },
-    );
+    let mut new = Entry::new(password.expose_secret().clone());
+    fields.apply(&mut new);
+    vault.insert(entry, new)?;
 
-    save_entries(passphrase, &storage)?;
-    run_hook(&Hook::PostSave, &HookEvent::NewEntry)?;
+    run_hook(config, &Hook::PreSave, &HookEvent::NewEntry, &[entry])?;
+    vault.save()?;
+    run_hook(config, &Hook::PostSave, &HookEvent::NewEntry, &[entry])?;
 
     Ok(())
 }
 
-fn list(no_keyring: bool) -> Result<(), Error> {
-    run_hook(&Hook::PreLoad, &HookEvent::ListEntries)?;
+fn list(config: &Config, format: Format) -> Result<(), Error> {
+    run_hook(config, &Hook::PreLoad, &HookEvent::ListEntries, &[])?;
 
-    let passphrase = get_passphrase("Enter passphrase: ", no_keyring)?;
-    let storage = load_entries(&passphrase)?;
-    for name in storage.entries.keys() {
+    let vault = unlock(config, "Enter passphrase: ")?;
+    run_hook(config, &Hook::PostLoad, &HookEvent::ListEntries, &[])?;
+    if format == Format::Json {
+        return print_json(&json!({ "entries": vault.list()? }));
+    }
+    for name in vault.list()? {
         println!("{}", name);
     }
     Ok(())
 }
 
-fn init(no_keyring: bool) -> Result<(), Error> {
-    fs::create_dir_all(storage_dir()?)?;
-    let path = entries_file()?;
-    if fs::metadata(path).is_err() {
-        File::create(entries_file()?)?;
-        let passphrase = get_passphrase("Passphrase: ", no_keyring)?;
-        let entries: Storage = toml::from_str("")?;
-        save_entries(passphrase, &entries)?
+fn init(config: &Config) -> Result<(), Error> {
+    fs::create_dir_all(storage_dir(config)?)?;
+    if !backend::open(config)?.exists()? {
+        let passphrase = get_passphrase(config, "Passphrase: ")?;
+        let mut vault = Vault::create(config, passphrase)?;
+        run_hook(config, &Hook::PreSave, &HookEvent::InitStorage, &[])?;
+        vault.save()?;
+        run_hook(config, &Hook::PostSave, &HookEvent::InitStorage, &[])?;
     }
     Ok(())
 }
 
-fn get_passphrase(prompt: &str, no_keyring: bool) -> Result<Secret<String>> {
-    if no_keyring {
-        let passphrase = rpassword::prompt_password_stdout(prompt)?;
-        Ok(Secret::new(passphrase))
-    } else {
-        get_passphrase_keyring(prompt)
+fn rekey(config: &Config) -> Result<()> {
+    run_hook(config, &Hook::PreLoad, &HookEvent::RekeyStorage, &[])?;
+    let mut vault = unlock(config, "Enter passphrase: ")?;
+    run_hook(config, &Hook::PostLoad, &HookEvent::RekeyStorage, &[])?;
+
+    let new_passphrase = rpassword::prompt_password_stdout("New passphrase: ")?;
+    let repeated = rpassword::prompt_password_stdout("Repeat new passphrase: ")?;
+    if new_passphrase != repeated {
+        return Err(anyhow!("passphrases don't match"));
     }
+    let new_passphrase = Secret::new(new_passphrase);
+
+    run_hook(config, &Hook::PreSave, &HookEvent::RekeyStorage, &[])?;
+    vault.set_passphrase(new_passphrase.clone())?;
+    vault.save()?;
+    // the storage is rekeyed at this point, so a keyring failure only gets a warning and
+    // the post_save hook still runs
+    store_keyring(config, &new_passphrase);
+    run_hook(config, &Hook::PostSave, &HookEvent::RekeyStorage, &[])?;
+
+    Ok(())
 }
 
-/// Gets the passphrase from either the keyring or stdin (and stores it in the keyring)
-fn get_passphrase_keyring(prompt: &str) -> Result<Secret<String>> {
-    let username = &whoami::username();
-    let keyring = keyring::Keyring::new(KEYRING_APP_NAME, username);
+fn import(config: &Config, file: &str, overwrite: bool) -> Result<()> {
+    let imported: Storage = toml::from_str(&fs::read_to_string(file)?)
+        .map_err(|e| anyhow!("failed to parse {}: {}", file, e))?;
 
-    let passphrase = if let Ok(pw) = keyring.get_password() {
-        Secret::new(pw)
-    } else {
-        let passphrase = rpassword::prompt_password_stdout(prompt)?;
-        if keyring.set_password(&passphrase).is_err() {
-            anyhow!("Failed to store password in keyring");
+    run_hook(config, &Hook::PreLoad, &HookEvent::ImportEntries, &[])?;
+    let mut vault = unlock(config, "Enter passphrase: ")?;
+    run_hook(config, &Hook::PostLoad, &HookEvent::ImportEntries, &[])?;
+
+    let mut names = vec![];
+    for (name, entry) in imported.entries {
+        if vault.contains(&name)? && !overwrite {
+            eprintln!("Skipping {}, it already exists", name);
+            continue;
         }
+        vault.insert(&name, entry)?;
+        names.push(name);
+    }
+    if names.is_empty() {
+        println!("Imported 0 entries");
+        return Ok(());
+    }
+    names.sort();
+    let names: Vec<&str> = names.iter().map(String::as_str).collect();
 
-        Secret::new(passphrase)
-    };
+    run_hook(config, &Hook::PreSave, &HookEvent::ImportEntries, &names)?;
+    vault.save()?;
+    run_hook(config, &Hook::PostSave, &HookEvent::ImportEntries, &names)?;
+    println!("Imported {} entries", names.len());
+
+    Ok(())
+}
 
+/// Gets the passphrase from `passphrase_command`, the keyring or stdin (and stores it in the
+/// keyring)
+fn get_passphrase(config: &Config, prompt: &str) -> Result<Secret<String>> {
+    if let Some(passphrase) = command_passphrase(config)? {
+        return Ok(passphrase);
+    }
+    if let Some(passphrase) = keyring::passphrase(config)? {
+        return Ok(passphrase);
+    }
+    let passphrase = prompt_passphrase(prompt)?;
+    store_keyring(config, &passphrase);
     Ok(passphrase)
 }
 
-#[cfg(target_os = "linux")]
-use fork::{fork, Fork};
+/// Opens the vault of `config` and unlocks it, see `with_passphrase`
+fn unlock(config: &Config, prompt: &str) -> Result<Vault> {
+    let mut vault = Vault::open(config)?;
+    with_passphrase(config, prompt, |passphrase| vault.unlock(passphrase))?;
+    Ok(vault)
+}
+
+/// Calls `open` with the passphrase printed by `passphrase_command`, or else the one from
+/// the keyring, or typed in if there is none. The passphrase is only stored in the keyring
+/// once `open` accepted it. If the passphrase from the keyring is wrong (for example because
+/// the storage was rekeyed elsewhere), it's asked for instead and the keyring value can be
+/// replaced.
+fn with_passphrase<T>(
+    config: &Config,
+    prompt: &str,
+    mut open: impl FnMut(Secret<String>) -> Result<T, passage::Error>,
+) -> Result<T> {
+    if let Some(passphrase) = command_passphrase(config)? {
+        return Ok(open(passphrase)?);
+    }
+    if let Some(passphrase) = keyring::passphrase(config)? {
+        match open(passphrase) {
+            Ok(opened) => return Ok(opened),
+            Err(passage::Error::WrongPassphrase) => {
+                eprintln!("The passphrase stored in the keyring can't decrypt the storage");
+            }
+            Err(e) => return Err(e.into()),
+        }
+        let passphrase = prompt_passphrase(prompt)?;
+        let opened = open(passphrase.clone())?;
+        if confirm("Replace the passphrase in the keyring (y/N)? ")? {
+            store_keyring(config, &passphrase);
+        }
+        return Ok(opened);
+    }
+
+    let passphrase = prompt_passphrase(prompt)?;
+    let opened = open(passphrase.clone())?;
+    store_keyring(config, &passphrase);
+    Ok(opened)
+}
 
-#[cfg(target_os = "linux")]
-fn copy_to_clipbpard(decrypted: String) -> Result<(), Error> {
-    match fork() {
-        Ok(Fork::Child) => {
-            let mut ctx: ClipboardContext = ClipboardProvider::new()
-                .map_err(|e| anyhow!("failed to initialize clipboard provider: {}", e))?;
-            ctx.set_contents(decrypted)
-                .map_err(|e| anyhow!("failed to copy to clipboard: {}", e))?;
+/// Runs `passphrase_command` if one is set for the storage in use
+fn command_passphrase(config: &Config) -> Result<Option<Secret<String>>> {
+    config
+        .passphrase_command()
+        .map(|command| passage::secret_from_command(command, "passphrase"))
+        .transpose()
+        .map_err(Into::into)
+}
 
-            std::thread::sleep(std::time::Duration::from_secs(10));
+/// Asks for a passphrase on stdout, or on stderr if stdout carries JSON
+fn prompt_passphrase(prompt: &str) -> Result<Secret<String>> {
+    let passphrase = if PROMPT_ON_STDERR.load(Ordering::Relaxed) {
+        rpassword::prompt_password_stderr(prompt)?
+    } else {
+        rpassword::prompt_password_stdout(prompt)?
+    };
+    Ok(Secret::new(passphrase))
+}
 
-            ctx.set_contents("".to_owned())
-                .map_err(|e| anyhow!("failed to copy to clipboard: {}", e))?;
-        }
-        Err(_) => return Err(Error::msg("Failed to fork()")),
-        Ok(_) => {}
+/// Stores `passphrase` in the keyring, failing to do so is only a warning since the
+/// passphrase can always be typed in
+fn store_keyring(config: &Config, passphrase: &Secret<String>) {
+    if let Err(e) = keyring::store(config, passphrase) {
+        eprintln!(
+            "Warning: failed to store the passphrase in the keyring ({}), \
+             disable the keyring integration with --no-keyring or `passage config set keyring false`",
+            e
+        );
     }
-    Ok(())
 }
 
-#[cfg(not(target_os = "linux"))]
-fn copy_to_clipbpard(decrypted: String) -> Result<(), Error> {
-    let mut ctx: ClipboardContext = ClipboardProvider::new()
-        .map_err(|e| anyhow!("failed to initialize clipboard provider: {}", e))?;
-    ctx.set_contents(decrypted)
-        .map_err(|e| anyhow!("failed to copy to clipboard: {}", e))?;
-    Ok(())
+/// Asks a yes/no question on stdin, anything but "y" means no
+fn confirm(question: &str) -> Result<bool> {
+    if PROMPT_ON_STDERR.load(Ordering::Relaxed) {
+        eprint!("{}", question);
+    } else {
+        print!("{}", question);
+        io::stdout().flush()?;
+    }
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim().eq_ignore_ascii_case("y"))
 }
 
-fn show(entry: &str, on_screen: bool, no_keyring: bool) -> Result<()> {
-    run_hook(&Hook::PreLoad, &HookEvent::ShowEntry)?;
-    let passphrase = get_passphrase("Enter passphrase: ", no_keyring)?;
-    let storage = load_entries(&passphrase)?;
-    if storage.entries.contains_key(entry) {
-        let password = &storage
-            .entries
-            .get(entry)
-            .ok_or_else(|| anyhow!("entry {} not found", entry))?
-            .password;
-        if on_screen {
-            println!("{}", password);
-        } else {
-            copy_to_clipbpard(password.to_string())?;
+/// Shows the password of `entry`, JSON output has the other fields of the entry and only
+/// contains the password with `reveal`
+fn show(config: &Config, entry: &str, format: Format, reveal: bool) -> Result<()> {
+    run_hook(config, &Hook::PreLoad, &HookEvent::ShowEntry, &[entry])?;
+    let vault = unlock(config, "Enter passphrase: ")?;
+    run_hook(config, &Hook::PostLoad, &HookEvent::ShowEntry, &[entry])?;
+    let found = vault.get(entry)?;
+    if format == Format::Json {
+        let mut document = json!({
+            "name": entry,
+            "username": found.username,
+            "autotype": found.autotype,
+        });
+        if reveal {
+            document["password"] = json!(found.password);
         }
+        return print_json(&document);
+    }
+    let password = &found.password;
+    if config.show_on == ShowOn::Screen {
+        println!("{}", password);
     } else {
-        return Err(anyhow!("{} not found", entry));
+        clipboard::copy(&config.clipboard, password, config.clip_time)?;
     }
 
     Ok(())
 }
 
-fn edit(entry: &str, no_keyring: bool) -> Result<()> {
-    run_hook(&Hook::PreLoad, &HookEvent::ShowEntry)?;
-    let passphrase = get_passphrase("Enter passphrase: ", no_keyring)?;
-    let mut storage = load_entries(&passphrase)?;
-    if storage.entries.contains_key(entry) {
-        let password = rpassword::prompt_password_stdout(&format!("New password for {}: ", entry))?;
-        storage.entries.insert(entry.to_owned(), Entry { password });
-        save_entries(passphrase, &storage)?;
-        run_hook(&Hook::PostSave, &HookEvent::EditEntry)?;
+fn edit(config: &Config, entry: &str, fields: EntryFields) -> Result<()> {
+    run_hook(config, &Hook::PreLoad, &HookEvent::EditEntry, &[entry])?;
+    let mut vault = unlock(config, "Enter passphrase: ")?;
+    run_hook(config, &Hook::PostLoad, &HookEvent::EditEntry, &[entry])?;
+    let existing = vault.get_mut(entry)?;
+    if fields.is_empty() {
+        existing.password =
+            rpassword::prompt_password_stdout(&format!("New password for {}: ", entry))?;
     } else {
-        return Err(anyhow!("entry not found: {}", entry));
-    };
+        fields.apply(existing);
+    }
+    run_hook(config, &Hook::PreSave, &HookEvent::EditEntry, &[entry])?;
+    vault.save()?;
+    run_hook(config, &Hook::PostSave, &HookEvent::EditEntry, &[entry])?;
 
     Ok(())
 }
 
-fn remove(entry: &str, no_keyring: bool) -> Result<()> {
-    run_hook(&Hook::PreLoad, &HookEvent::ShowEntry)?;
-    let passphrase = get_passphrase("Enter passphrase: ", no_keyring)?;
-    let mut storage = load_entries(&passphrase)?;
-    if storage.entries.remove(entry).is_some() {
-        save_entries(passphrase, &storage)?;
-        run_hook(&Hook::PostSave, &HookEvent::RemoveEntry)?;
-    } else {
-        return Err(anyhow!("entry not found: {}", entry));
+fn type_entry(config: &Config, entry: &str, sequence: Option<String>) -> Result<()> {
+    run_hook(config, &Hook::PreLoad, &HookEvent::TypeEntry, &[entry])?;
+    let vault = unlock(config, "Enter passphrase: ")?;
+    run_hook(config, &Hook::PostLoad, &HookEvent::TypeEntry, &[entry])?;
+    let entry = vault.get(entry)?;
+    let sequence = sequence.unwrap_or_else(|| autotype::default_sequence(&config.autotype, entry));
+    autotype::autotype(&config.autotype, entry, &sequence)
+}
+
+fn remove(config: &Config, entry: &str) -> Result<()> {
+    run_hook(config, &Hook::PreLoad, &HookEvent::RemoveEntry, &[entry])?;
+    let mut vault = unlock(config, "Enter passphrase: ")?;
+    run_hook(config, &Hook::PostLoad, &HookEvent::RemoveEntry, &[entry])?;
+    vault.remove(entry)?;
+    run_hook(config, &Hook::PreSave, &HookEvent::RemoveEntry, &[entry])?;
+    vault.save()?;
+    run_hook(config, &Hook::PostSave, &HookEvent::RemoveEntry, &[entry])?;
+
+    Ok(())
+}
+
+fn move_entry(config: &Config, from: &str, to: &str) -> Result<()> {
+    run_hook(config, &Hook::PreLoad, &HookEvent::MoveEntry, &[from, to])?;
+    let mut vault = unlock(config, "Enter passphrase: ")?;
+    run_hook(config, &Hook::PostLoad, &HookEvent::MoveEntry, &[from, to])?;
+    if vault.contains(to)? {
+        return Err(passage::Error::EntryExists(to.to_string()).into());
+    }
+    let entry = vault.remove(from)?;
+    vault.insert(to, entry)?;
+    run_hook(config, &Hook::PreSave, &HookEvent::MoveEntry, &[from, to])?;
+    vault.save()?;
+    run_hook(config, &Hook::PostSave, &HookEvent::MoveEntry, &[from, to])?;
+
+    Ok(())
+}
+
+/// Returns the names of the entries in `config`'s storage matching `pattern`, sorted
+fn find_entries(config: &Config, pattern: &str, prompt: &str) -> Result<Vec<String>> {
+    run_hook(config, &Hook::PreLoad, &HookEvent::FindEntries, &[])?;
+    let vault = unlock(config, prompt)?;
+    run_hook(config, &Hook::PostLoad, &HookEvent::FindEntries, &[])?;
+
+    let pattern = pattern.to_lowercase();
+    let mut names: Vec<String> = vault
+        .storage()?
+        .entries
+        .iter()
+        .filter(|(name, entry)| {
+            name.to_lowercase().contains(&pattern)
+                || entry
+                    .username
+                    .as_ref()
+                    .is_some_and(|username| username.to_lowercase().contains(&pattern))
+        })
+        .map(|(name, _)| name.clone())
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+fn find(config: &Config, pattern: &str, all_vaults: bool, format: Format) -> Result<()> {
+    let as_json = |vault: Option<&String>, names: Vec<String>| {
+        names
+            .into_iter()
+            .map(|name| json!({ "vault": vault, "entry": name }))
+            .collect::<Vec<_>>()
     };
+    if !all_vaults {
+        let names = find_entries(config, pattern, "Enter passphrase: ")?;
+        if format == Format::Json {
+            return print_json(&json!({ "matches": as_json(config.vault.as_ref(), names) }));
+        }
+        for name in names {
+            println!("{}", name);
+        }
+        return Ok(());
+    }
 
+    if config.vaults.is_empty() {
+        return Err(anyhow!(
+            "there are no vaults, add one with `passage vault add`"
+        ));
+    }
+    let mut matches = vec![];
+    for vault in config.vaults.keys() {
+        let vault_config = config.for_vault(vault)?;
+        if !backend::open(&vault_config)?.exists()? {
+            eprintln!("Skipping {}, its storage isn't initialized", vault);
+            continue;
+        }
+        let prompt = format!("Enter passphrase for {}: ", vault);
+        let names = find_entries(&vault_config, pattern, &prompt)?;
+        if format == Format::Json {
+            matches.extend(as_json(Some(vault), names));
+            continue;
+        }
+        for name in names {
+            println!("{}: {}", vault, name);
+        }
+    }
+    if format == Format::Json {
+        print_json(&json!({ "matches": matches }))?;
+    }
     Ok(())
 }
 
-fn info() -> Result<()> {
-    let storage_path = entries_file()?;
-    if fs::metadata(storage_path.clone()).is_ok() {
-        println!("Storage file: {}", storage_path);
+/// Copies `entry` from the vault `from` to the vault `to`, running the hooks of both
+fn copy_entry(config: &Config, entry: &str, from: &str, to: &str, overwrite: bool) -> Result<()> {
+    if from == to {
+        return Err(anyhow!("source and destination are the same vault"));
+    }
+    let source = config.for_vault(from)?;
+    let destination = config.for_vault(to)?;
+
+    run_hook(&source, &Hook::PreLoad, &HookEvent::CopyEntry, &[entry])?;
+    let vault = unlock(&source, &format!("Enter passphrase for {}: ", from))?;
+    run_hook(&source, &Hook::PostLoad, &HookEvent::CopyEntry, &[entry])?;
+    let copied = vault
+        .storage()?
+        .entries
+        .get(entry)
+        .ok_or_else(|| passage::Error::EntryNotFound(format!("{} (in {})", entry, from)))?
+        .clone();
+
+    run_hook(
+        &destination,
+        &Hook::PreLoad,
+        &HookEvent::CopyEntry,
+        &[entry],
+    )?;
+    let mut vault = unlock(&destination, &format!("Enter passphrase for {}: ", to))?;
+    run_hook(
+        &destination,
+        &Hook::PostLoad,
+        &HookEvent::CopyEntry,
+        &[entry],
+    )?;
+    if vault.contains(entry)? && !overwrite {
+        return Err(passage::Error::EntryExists(format!("{} (in {})", entry, to)).into());
+    }
+    vault.insert(entry, copied)?;
+    run_hook(
+        &destination,
+        &Hook::PreSave,
+        &HookEvent::CopyEntry,
+        &[entry],
+    )?;
+    vault.save()?;
+    run_hook(
+        &destination,
+        &Hook::PostSave,
+        &HookEvent::CopyEntry,
+        &[entry],
+    )?;
+    println!("Copied {} from {} to {}", entry, from, to);
+
+    Ok(())
+}
+
+/// Opens the terminal interface, the storage is unlocked once for the whole session
+fn browse(config: &Config) -> Result<()> {
+    run_hook(config, &Hook::PreLoad, &HookEvent::BrowseEntries, &[])?;
+    let vault = unlock(config, "Enter passphrase: ")?;
+    run_hook(config, &Hook::PostLoad, &HookEvent::BrowseEntries, &[])?;
+    tui::run(config, vault)
+}
+
+fn merge_files(
+    config: &Config,
+    files: [&str; 3],
+    out: Option<&str>,
+    prefer: Option<Side>,
+) -> Result<()> {
+    run_hook(config, &Hook::PreLoad, &HookEvent::MergeStorage, &[])?;
+    let mut encrypted = vec![];
+    for file in &files {
+        encrypted.push(fs::read(file).map_err(|e| anyhow!("failed to read {}: {}", file, e))?);
+    }
+    let (passphrase, versions) = with_passphrase(config, "Enter passphrase: ", |passphrase| {
+        let versions = encrypted
+            .iter()
+            .map(|encrypted| Storage::decrypt(encrypted, &passphrase))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok((passphrase, versions))
+    })?;
+    run_hook(config, &Hook::PostLoad, &HookEvent::MergeStorage, &[])?;
+
+    let merged = merge::merge(&versions[0], &versions[1], &versions[2]);
+    let conflicts = merged.conflicts.len();
+    let storage = merge::resolve(merged, |conflict| {
+        prefer.map_or_else(|| merge::prompt(conflict), Ok)
+    })?;
+
+    let encrypted = storage.encrypt(&passphrase)?;
+    fs::write(out.unwrap_or(files[1]), encrypted)?;
+    println!(
+        "Merged {} entries, {} conflicts",
+        storage.entries.len(),
+        conflicts
+    );
+    Ok(())
+}
+
+fn vault_add(name: &str, storage_folder: Option<String>) -> Result<()> {
+    let storage_folder = match storage_folder {
+        Some(folder) => std::env::current_dir()?.join(folder),
+        None => ProjectDirs::from("", "", "passage")
+            .ok_or_else(|| anyhow!("couldn't determine project storage folder"))?
+            .data_dir()
+            .join("vaults")
+            .join(name),
+    };
+    let storage_folder = storage_folder.display().to_string();
+    config::add_vault(name, &storage_folder)?;
+    println!("Added vault {} ({})", name, storage_folder);
+    Ok(())
+}
+
+fn vault_list(config: &Config) {
+    for (name, vault) in &config.vaults {
+        let marker = if config.vault.as_ref() == Some(name) {
+            '*'
+        } else {
+            ' '
+        };
+        println!("{} {} ({})", marker, name, vault.storage_folder);
+    }
+}
+
+fn vault_use(config: &Config, name: Option<&str>) -> Result<()> {
+    if let Some(name) = name {
+        if !config.vaults.contains_key(name) {
+            return Err(passage::Error::UnknownVault(name.to_string()).into());
+        }
+        config::set("vault", name)?;
+        println!("Using vault {}", name);
     } else {
-        println!("Storage file doesn't exist yet, run `passage init` to create it");
+        config::unset("vault")?;
+        println!("Using the default storage folder");
+    }
+    Ok(())
+}
+
+fn vault_remove(name: &str) -> Result<()> {
+    // forgetting the passphrase needs the settings of the vault, which can't be had from a
+    // broken config
+    let vault = Config::load()
+        .and_then(|config| config.for_vault(name))
+        .ok();
+    config::remove_vault(name)?;
+    // the passphrase might never have been stored or the keyring isn't available, the
+    // vault is gone from the config either way
+    if let Some(vault) = vault {
+        let _ = keyring::forget(&vault);
     }
+    println!(
+        "Removed vault {}, its storage folder was left in place",
+        name
+    );
+    Ok(())
+}
 
-    let hooks_dir = hooks_dir()?;
+fn info(config: &Config, format: Format) -> Result<()> {
+    if format == Format::Json {
+        return print_json(&info_json(config)?);
+    }
+    if let Some(vault) = &config.vault {
+        println!("Vault: {}", vault);
+    }
+    match config.storage_backend() {
+        BackendKind::File => {
+            let storage_path = entries_file(config)?;
+            if fs::metadata(storage_path.clone()).is_ok() {
+                println!("Storage file: {}", storage_path);
+            } else {
+                println!("Storage file doesn't exist yet, run `passage init` to create it");
+            }
+        }
+        BackendKind::S3 => {
+            let s3 = config.s3_config();
+            println!(
+                "Storage object: s3://{}/{} ({})",
+                s3.bucket.as_deref().unwrap_or_default(),
+                s3.key,
+                s3.endpoint.as_deref().unwrap_or(&s3.region)
+            );
+        }
+        BackendKind::WebDav => {
+            let url = config.webdav_config().url.as_deref();
+            println!("Storage URL: {}", url.unwrap_or_default());
+        }
+    }
+
+    let hooks_dir = hooks_dir(config)?;
     if fs::metadata(&hooks_dir).is_ok() {
         println!("Hooks directory: {}", hooks_dir);
+        for hook in &Hook::ALL {
+            let scripts: Vec<String> = hooks::scripts(config, hook)?
+                .into_iter()
+                .map(|script| {
+                    if script.executable {
+                        script.name
+                    } else {
+                        format!("{} (not executable, skipped)", script.name)
+                    }
+                })
+                .collect();
+            if !scripts.is_empty() {
+                println!("  {}: {}", hook.name(), scripts.join(", "));
+            }
+        }
     } else {
         println!("Hooks directory does not exist yet: {}", hooks_dir);
     }
     Ok(())
 }
 
-fn run_hook(hook: &Hook, event: &HookEvent) -> Result<()> {
-    let path = Path::new(&hooks_dir()?)
-        .join(hook.name())
-        .display()
-        .to_string();
-    if fs::metadata(&path).is_ok() {
-        println!("Running {} hook", hook.name());
-        let storage_dir = storage_dir()?;
-        let output = Command::new(path)
-            .args(&[event.name()])
-            .current_dir(storage_dir)
-            .output()?;
+/// Returns what `info` prints as a JSON document
+fn info_json(config: &Config) -> Result<serde_json::Value> {
+    let storage = match config.storage_backend() {
+        BackendKind::File => {
+            let path = entries_file(config)?;
+            json!({ "backend": "file", "exists": fs::metadata(&path).is_ok(), "path": path })
+        }
+        BackendKind::S3 => {
+            let s3 = config.s3_config();
+            json!({
+                "backend": "s3",
+                "endpoint": s3.endpoint,
+                "region": s3.region,
+                "bucket": s3.bucket,
+                "key": s3.key,
+            })
+        }
+        BackendKind::WebDav => json!({ "backend": "webdav", "url": config.webdav_config().url }),
+    };
+
+    let hooks_dir = hooks_dir(config)?;
+    let mut scripts = serde_json::Map::new();
+    for hook in &Hook::ALL {
+        let found: Vec<_> = hooks::scripts(config, hook)?
+            .into_iter()
+            .map(|script| json!({ "name": script.name, "executable": script.executable }))
+            .collect();
+        scripts.insert(hook.name(), json!(found));
+    }
+    Ok(json!({
+        "vault": config.vault,
+        "storage": storage,
+        "hooks": {
+            "directory": hooks_dir,
+            "exists": fs::metadata(&hooks_dir).is_ok(),
+            "scripts": scripts,
+        },
+    }))
+}
 
-        let stdout = String::from_utf8(output.stdout)?;
-        let stderr = String::from_utf8(output.stderr)?;
+/// Returns the default storage folder and every vault, with a label for each of them
+fn all_storages(config: &Config) -> Result<Vec<(String, Config)>> {
+    let mut default = config.clone();
+    default.vault = None;
+    let mut storages = vec![("default storage".to_string(), default)];
+    for name in config.vaults.keys() {
+        storages.push((format!("vault {}", name), config.for_vault(name)?));
+    }
+    Ok(storages)
+}
 
-        for line in stdout.lines() {
-            println!("{}: {}", hook.name(), line);
+fn keyring_check(config: &Config, all: bool, format: Format) -> Result<()> {
+    if format == Format::Json {
+        let storages = if all {
+            all_storages(config)?
+                .into_iter()
+                .map(|(_, storage)| storage)
+                .collect()
+        } else {
+            vec![config.clone()]
+        };
+        let mut checked = vec![];
+        for storage in storages {
+            checked.push(json!({
+                "vault": storage.vault,
+                "passphrase_stored": keyring::is_stored(&storage)?,
+            }));
         }
-        for line in stderr.lines() {
-            println!("{}: {}", hook.name(), line);
+        return print_json(&json!({ "storages": checked }));
+    }
+    if all {
+        for (label, storage) in all_storages(config)? {
+            if keyring::is_stored(&storage)? {
+                println!("{}: passphrase stored", label);
+            } else {
+                println!("{}: no passphrase stored", label);
+            }
         }
+    } else if keyring::is_stored(config)? {
+        println!("Keyring integration seems fine");
+    } else {
+        println!("Keyring integration seems fine, no passphrase stored yet");
+    }
+    Ok(())
+}
 
-        if !output.status.success() {
-            anyhow!("{} hook failed", hook.name());
+fn keyring_forget(config: &Config, all: bool) -> Result<()> {
+    if all {
+        for (label, storage) in all_storages(config)? {
+            if keyring::forget(&storage)? {
+                println!("Forgot the passphrase of the {}", label);
+            }
+        }
+        // entry of versions which didn't tell storage folders apart
+        if keyring::forget_legacy()? {
+            println!("Forgot the passphrase stored by older versions");
         }
+    } else if keyring::forget(config)? {
+        println!("Forgot the passphrase");
+    } else {
+        println!("No passphrase stored");
     }
-
     Ok(())
 }
 
-fn keyring_check() -> Result<()> {
-    let username = &whoami::username();
-    let keyring = keyring::Keyring::new(KEYRING_APP_NAME, username);
-    if keyring.get_password().is_err() {
-        anyhow!("Failed to access password in keyring");
+/// Prints the names the completion scripts offer, one per line. Entries are only listed
+/// if the passphrase comes from `passphrase_command` or the keyring, this never prompts and
+/// doesn't run any hooks.
+fn complete(opt: CompleteOpt) -> Result<()> {
+    let mut config = Config::load()?;
+    config.use_vault(opt.vault)?;
+    match opt.names {
+        Names::Entries => {
+            let passphrase = match command_passphrase(&config)? {
+                Some(passphrase) => Some(passphrase),
+                None => keyring::passphrase(&config)?,
+            };
+            let Some(passphrase) = passphrase else {
+                return Ok(());
+            };
+            let mut vault = Vault::open(&config)?;
+            vault.unlock(passphrase)?;
+            for name in vault.list()? {
+                println!("{}", name);
+            }
+        }
+        Names::Vaults => {
+            for name in config.vaults.keys() {
+                println!("{}", name);
+            }
+        }
     }
-    println!("Keyring integration seems fine");
     Ok(())
 }
 
-fn keyring_forget() -> Result<()> {
-    let username = &whoami::username();
-    let keyring = keyring::Keyring::new(KEYRING_APP_NAME, username);
-    if keyring.delete_password().is_err() {
-        anyhow!("Failed to delete password from keyring");
+/// Prints the man page of `command`, or writes all of them to `dir`
+fn man_pages(dir: Option<&str>, command: &[String]) -> Result<()> {
+    if let Some(dir) = dir {
+        for path in man::write_all(&Opt::clap(), dir.as_ref())? {
+            println!("{}", path.display());
+        }
+    } else {
+        print!("{}", man::page(&Opt::clap(), command)?);
     }
     Ok(())
 }
 
-fn main() -> Result<(), Error> {
-    let opt = Opt::from_args();
-    match opt.cmd {
-        Cmd::New => new_entry(opt.no_keyring),
-        Cmd::List => list(opt.no_keyring),
-        Cmd::Init => init(opt.no_keyring),
-        Cmd::Show { entry, on_screen } => show(&entry, on_screen, opt.no_keyring),
-        Cmd::Edit { entry } => edit(&entry, opt.no_keyring),
-        Cmd::Remove { entry } => remove(&entry, opt.no_keyring),
-        Cmd::Info => info(),
-        Cmd::Keyring(ko) => match ko {
-            KeyringOpt::Check => keyring_check(),
-            KeyringOpt::Forget => keyring_forget(),
-        },
+fn config_command(co: ConfigOpt) -> Result<()> {
+    match co {
+        ConfigOpt::Get { key } => println!("{}", config::get(&key)?),
+        ConfigOpt::Set { key, value } => config::set(&key, &value)?,
+        ConfigOpt::List => {
+            for (key, value) in config::list()? {
+                println!("{} = {}", key, value);
+            }
+        }
     }
+    Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Exit code for invalid command line arguments, the codes of the other errors are
+/// defined by `passage::Error::exit_code`
+const EXIT_USAGE: i32 = 2;
+
+/// Prints `error` to stderr and returns the exit code for it
+fn report(error: &Error, json: bool) -> i32 {
+    let passage_error = error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<passage::Error>());
+    let exit_code = passage_error.map_or(1, passage::Error::exit_code);
+    if json {
+        let report = serde_json::json!({
+            "error": {
+                "kind": passage_error.map_or("other", passage::Error::kind),
+                "message": format!("{:#}", error),
+                "exit_code": exit_code,
+            }
+        });
+        eprintln!("{}", report);
+    } else {
+        eprintln!("Error: {:?}", error);
+    }
+    exit_code
+}
 
-    #[test]
-    fn test_ok() {
-        let text = b"this is plain";
-        let passphrase = Secret::new("secret".to_string());
-        let encrypted = encrypt(text, passphrase.clone()).unwrap();
-        let decrypted = decrypt(&encrypted, &passphrase).unwrap();
-        assert_eq!(decrypted, text);
+fn main() {
+    if std::env::args_os()
+        .nth(1)
+        .is_some_and(|arg| arg == "__complete")
+    {
+        let opt = CompleteOpt::from_iter(std::env::args_os().skip(1));
+        if let Err(e) = complete(opt) {
+            std::process::exit(report(&e, false));
+        }
+        return;
+    }
+    let opt = match Opt::from_args_safe() {
+        Ok(opt) => opt,
+        Err(e) if e.use_stderr() => {
+            eprintln!("{}", e.message);
+            std::process::exit(EXIT_USAGE);
+        }
+        Err(e) => e.exit(),
+    };
+    let json = opt.json || opt.output == Format::Json;
+    if let Err(e) = run(opt) {
+        std::process::exit(report(&e, json));
     }
+}
 
-    #[test]
-    fn test_entry_serialization() {
-        let s: Storage = toml::from_str("[foo] \n password = 'bar'").unwrap();
-        assert_eq!(s.entries.get("foo").unwrap().password, "bar");
+fn run(opt: Opt) -> Result<(), Error> {
+    PROMPT_ON_STDERR.store(opt.output == Format::Json, Ordering::Relaxed);
+    hooks::print_to_stderr(opt.output == Format::Json);
+    // these only touch the config file, so they keep working (and can repair it) when the
+    // config file or the environment hold invalid values
+    let cmd = match opt.cmd {
+        Cmd::Config(co) => return config_command(co),
+        Cmd::Vault(VaultOpt::Add {
+            name,
+            storage_folder,
+        }) => return vault_add(&name, storage_folder),
+        Cmd::Vault(VaultOpt::Remove { name }) => return vault_remove(&name),
+        cmd => cmd,
+    };
+    let mut config = Config::load()?;
+    if opt.no_keyring {
+        config.keyring = false;
+    }
+    // vault commands need to work even if the configured vault doesn't exist
+    if !matches!(cmd, Cmd::Vault(_)) {
+        config.use_vault(opt.vault)?;
+    }
+    match cmd {
+        Cmd::New { fields } => new_entry(&config, fields),
+        Cmd::List => list(&config, opt.output),
+        Cmd::Init => init(&config),
+        Cmd::Show {
+            entry,
+            on_screen,
+            clipboard,
+            clip_time,
+            primary,
+            reveal,
+        } => {
+            if let Some(clip_time) = clip_time {
+                config.clip_time = clip_time;
+            }
+            if primary {
+                config.clipboard.primary = true;
+            }
+            if on_screen {
+                config.show_on = ShowOn::Screen;
+            } else if clipboard {
+                config.show_on = ShowOn::Clipboard;
+            }
+            show(&config, &entry, opt.output, reveal)
+        }
+        Cmd::Edit { entry, fields } => edit(&config, &entry, fields),
+        Cmd::Type { entry, sequence } => type_entry(&config, &entry, sequence),
+        Cmd::Find {
+            pattern,
+            all_vaults,
+        } => find(&config, &pattern, all_vaults, opt.output),
+        Cmd::Remove { entry } => remove(&config, &entry),
+        Cmd::Mv { from, to } => move_entry(&config, &from, &to),
+        Cmd::Cp {
+            entry,
+            from,
+            to,
+            overwrite,
+        } => copy_entry(&config, &entry, &from, &to, overwrite),
+        Cmd::Rekey => rekey(&config),
+        Cmd::Import { file, overwrite } => import(&config, &file, overwrite),
+        Cmd::Merge {
+            base,
+            ours,
+            theirs,
+            out,
+            prefer,
+        } => merge_files(&config, [&base, &ours, &theirs], out.as_deref(), prefer),
+        Cmd::Info => info(&config, opt.output),
+        Cmd::Keyring(ko) => match ko {
+            KeyringOpt::Check { all } => keyring_check(&config, all, opt.output),
+            KeyringOpt::Forget { all } => keyring_forget(&config, all),
+        },
+        Cmd::Git(go) => match go {
+            GitOpt::Init { remote } => git::init(&config, remote.as_deref()),
+            GitOpt::Sync { prefer } => git::sync(&config, prefer),
+            GitOpt::Log if opt.output == Format::Json => {
+                print_json(&json!({ "commits": git::log(&config)? }))
+            }
+            GitOpt::Log => {
+                for commit in git::log(&config)? {
+                    println!("{} {} {}", commit.id, commit.date, commit.message);
+                }
+                Ok(())
+            }
+        },
+        Cmd::Vault(VaultOpt::List) => {
+            vault_list(&config);
+            Ok(())
+        }
+        Cmd::Vault(VaultOpt::Use { name }) => vault_use(&config, name.as_deref()),
+        // handled before the config is loaded
+        Cmd::Config(_) | Cmd::Vault(VaultOpt::Add { .. } | VaultOpt::Remove { .. }) => Ok(()),
+        Cmd::Completions { shell } => completions::generate(Opt::clap(), shell, &mut io::stdout()),
+        Cmd::Tui => browse(&config),
+        Cmd::Man { dir, command } => man_pages(dir.as_deref(), &command),
     }
 }
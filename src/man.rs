@@ -0,0 +1,381 @@
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use passage::config::{self, KEYS};
+use passage::hooks::Hook;
+use structopt::clap::{App, ErrorKind};
+
+/// One section of the help clap prints, like `OPTIONS`
+struct Section {
+    title: String,
+    lines: Vec<String>,
+}
+
+/// What the man pages are built from, taken from the `--help` output of a command
+struct Help {
+    about: String,
+    usage: String,
+    sections: Vec<Section>,
+}
+
+impl Help {
+    /// Asks clap for the help of `command` (e.g. `["vault", "add"]`), without wrapping
+    fn of(app: &App, command: &[String]) -> Result<Self> {
+        let args = std::iter::once("passage")
+            .chain(command.iter().map(String::as_str))
+            .chain(std::iter::once("--help"));
+        let text = match app.clone().set_term_width(0).get_matches_from_safe(args) {
+            Err(e) if e.kind == ErrorKind::HelpDisplayed => e.message,
+            _ => return Err(anyhow!("unknown command: passage {}", command.join(" "))),
+        };
+
+        let mut lines = text.lines().skip(1);
+        let about = lines.next().unwrap_or_default().to_string();
+        let mut sections: Vec<Section> = vec![];
+        for line in lines {
+            if let Some(title) = line.strip_suffix(':').filter(|_| !line.starts_with(' ')) {
+                sections.push(Section {
+                    title: title.to_string(),
+                    lines: vec![],
+                });
+            } else if let Some(section) = sections.last_mut().filter(|_| !line.is_empty()) {
+                section.lines.push(line.trim().to_string());
+            }
+        }
+        let usage = sections
+            .iter()
+            .position(|section| section.title == "USAGE")
+            .map(|i| sections.remove(i).lines.join(" "))
+            .unwrap_or_default();
+        Ok(Self {
+            about,
+            usage,
+            sections,
+        })
+    }
+
+    /// Returns the items of a section as pairs of the term and its description
+    fn items(&self, title: &str) -> Vec<(&str, &str)> {
+        self.sections
+            .iter()
+            .filter(|section| section.title == title)
+            .flat_map(|section| &section.lines)
+            .map(|line| {
+                line.find("  ").map_or((line.as_str(), ""), |i| {
+                    (&line[..i], line[i..].trim_start())
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the names of the subcommands, without clap's `help`
+    fn subcommands(&self) -> Vec<String> {
+        self.items("SUBCOMMANDS")
+            .into_iter()
+            .map(|(name, _)| name.to_string())
+            .filter(|name| name != "help")
+            .collect()
+    }
+}
+
+/// Escapes text so roff prints it as it is
+fn escape(text: &str) -> String {
+    let text = text.replace('\\', "\\e").replace('-', "\\-");
+    if text.starts_with('.') || text.starts_with('\'') {
+        format!("\\&{}", text)
+    } else {
+        text
+    }
+}
+
+/// Name of the page of `command`, like `passage-vault-add`
+fn page_name(command: &[String]) -> String {
+    std::iter::once("passage")
+        .chain(command.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+fn header(page: &mut String, name: &str, about: &str) {
+    let _ = writeln!(
+        page,
+        ".TH {} 1 \"\" \"passage {}\" \"User Commands\"",
+        escape(&name.to_uppercase()),
+        env!("CARGO_PKG_VERSION")
+    );
+    let _ = writeln!(page, ".SH NAME\n{} \\- {}", escape(name), escape(about));
+}
+
+fn item(page: &mut String, term: &str, description: &str) {
+    let _ = writeln!(page, ".TP\n\\fB{}\\fR", escape(term));
+    if !description.is_empty() {
+        let _ = writeln!(page, "{}", escape(description));
+    }
+}
+
+fn items(page: &mut String, title: &str, items: &[(&str, &str)]) {
+    if items.is_empty() {
+        return;
+    }
+    let _ = writeln!(page, ".SH {}", title);
+    for (term, description) in items {
+        item(page, term, description);
+    }
+}
+
+fn paragraphs(page: &mut String, title: &str, paragraphs: &[&str]) {
+    let _ = writeln!(page, ".SH {}", title);
+    for (i, text) in paragraphs.iter().enumerate() {
+        if i > 0 {
+            page.push_str(".PP\n");
+        }
+        let _ = writeln!(page, "{}", escape(text));
+    }
+}
+
+fn paragraph(page: &mut String, text: &str) {
+    let _ = writeln!(page, ".PP\n{}", escape(text));
+}
+
+/// The `HOOKS` section of the page of `passage`, which describes what isn't part of the
+/// `--help` output
+fn hooks(page: &mut String) {
+    paragraphs(
+        page,
+        "HOOKS",
+        &[
+            "Executable scripts in the hooks directory of the storage folder (see passage info) \
+             are run before or after the storage gets loaded or saved. The script named after \
+             the hook runs first, followed by the scripts in a folder named after the hook with \
+             a .d suffix, in lexical order.",
+        ],
+    );
+    for hook in &Hook::ALL {
+        let description = match hook {
+            Hook::PreLoad => "Runs before the storage gets loaded.",
+            Hook::PostLoad => "Runs after the storage was loaded.",
+            Hook::PreSave => "Runs before changes get written, it can veto them.",
+            Hook::PostSave => "Runs after changes were written.",
+        };
+        item(page, &hook.name(), description);
+    }
+    paragraph(
+        page,
+        "Hooks run inside the storage folder and get the name of the event (like edit_entry) as \
+         their first argument. Secrets are never passed to hooks. They get these environment \
+         variables, the same information is written to their stdin as a JSON document:",
+    );
+    for (name, description) in &[
+        ("PASSAGE_HOOK", "The name of the hook, like post_save."),
+        ("PASSAGE_EVENT", "The name of the event, like edit_entry."),
+        ("PASSAGE_COMMAND", "The subcommand, like edit."),
+        (
+            "PASSAGE_ENTRIES",
+            "The names of the entries the command works on, one per line.",
+        ),
+        ("PASSAGE_STORAGE_FILE", "The path to entries.toml.age."),
+        ("PASSAGE_VERSION", "The version of passage."),
+    ] {
+        item(page, name, description);
+    }
+    paragraph(
+        page,
+        &format!(
+            "If a hook exits with a non-zero status or runs longer than hooks.timeout seconds, \
+             the command is aborted with exit status {}. With hooks.<hook>.on_failure set to \
+             warn, only a warning is printed instead.",
+            passage::Error::HookFailed(String::new()).exit_code()
+        ),
+    );
+}
+
+/// The `ENVIRONMENT`, `FILES` and `EXIT STATUS` sections of the page of `passage`
+fn environment(page: &mut String) {
+    let mut environment = vec![
+        (
+            "PASSAGE_CONFIG".to_string(),
+            "The path to the config file.".to_string(),
+        ),
+        (
+            "AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY, AWS_SESSION_TOKEN".to_string(),
+            "The credentials of the s3 backend.".to_string(),
+        ),
+    ];
+    environment.extend(KEYS.iter().map(|key| {
+        (
+            config::env_var(key),
+            format!("Overrides the {} setting of the config file.", key),
+        )
+    }));
+    let environment: Vec<(&str, &str)> = environment
+        .iter()
+        .map(|(name, description)| (name.as_str(), description.as_str()))
+        .collect();
+    items(page, "ENVIRONMENT", &environment);
+
+    items(
+        page,
+        "FILES",
+        &[
+            (
+                "~/.config/passage/config.toml",
+                "The config file on Linux, see passage config.",
+            ),
+            (
+                "~/.local/share/passage/entries.toml.age",
+                "The encrypted storage in the default storage folder on Linux.",
+            ),
+            (
+                "~/.local/share/passage/hooks",
+                "The hooks directory of the default storage folder on Linux.",
+            ),
+        ],
+    );
+
+    let codes = exit_codes();
+    let codes: Vec<(String, &str)> = codes
+        .iter()
+        .map(|(code, kind, description)| {
+            let code = kind.map_or_else(|| code.to_string(), |kind| format!("{} ({})", code, kind));
+            (code, *description)
+        })
+        .collect();
+    let codes: Vec<(&str, &str)> = codes
+        .iter()
+        .map(|(code, description)| (code.as_str(), *description))
+        .collect();
+    items(page, "EXIT STATUS", &codes);
+}
+
+/// The exit codes with the kind of error they stand for, taken from the errors themselves
+/// so they can't get out of sync
+fn exit_codes() -> Vec<(i32, Option<&'static str>, &'static str)> {
+    use passage::Error;
+
+    let mut codes = vec![
+        (0, None, "Success."),
+        (1, Some("other, io"), "Any other error."),
+        (crate::EXIT_USAGE, None, "Invalid command line arguments."),
+    ];
+    for (error, description) in [
+        (
+            Error::NotInitialized,
+            "The storage doesn't exist yet, run passage init.",
+        ),
+        (Error::AlreadyInitialized, "The storage exists already."),
+        (
+            Error::WrongPassphrase,
+            "The passphrase can't decrypt the storage.",
+        ),
+        (
+            Error::EntryNotFound(String::new()),
+            "There is no entry with that name.",
+        ),
+        (
+            Error::EntryExists(String::new()),
+            "There is an entry with that name already.",
+        ),
+        (
+            Error::HookFailed(String::new()),
+            "A hook script failed and its hook aborts the command.",
+        ),
+        (
+            Error::Conflict,
+            "Someone else changed the storage in the meantime, try again.",
+        ),
+        (
+            Error::StorageLocked(String::new()),
+            "Another process holds the lock on the storage.",
+        ),
+        (
+            Error::Keyring(String::new()),
+            "The keyring couldn't be accessed.",
+        ),
+        (
+            Error::UnknownVault(String::new()),
+            "There is no vault with that name.",
+        ),
+    ] {
+        codes.push((error.exit_code(), Some(error.kind()), description));
+    }
+    codes
+}
+
+/// Returns the man page of `command` (e.g. `["vault", "add"]`, empty for `passage`
+/// itself) in roff
+pub fn page(app: &App, command: &[String]) -> Result<String> {
+    let help = Help::of(app, command)?;
+    let name = page_name(command);
+    let mut page = String::new();
+    header(&mut page, &name, &help.about);
+    let _ = writeln!(page, ".SH SYNOPSIS\n{}", escape(&help.usage));
+    if command.is_empty() {
+        paragraphs(
+            &mut page,
+            "DESCRIPTION",
+            &[
+                "passage keeps passwords in a single file encrypted with age, using a \
+                 passphrase. The passphrase can be stored in the keyring of the operating \
+                 system.",
+                "Each command has its own man page, like passage-show(1).",
+            ],
+        );
+    }
+
+    let mut options = help.items("FLAGS");
+    options.extend(help.items("OPTIONS"));
+    items(&mut page, "OPTIONS", &options);
+    items(&mut page, "ARGUMENTS", &help.items("ARGS"));
+    let subcommands: Vec<(&str, &str)> = help
+        .items("SUBCOMMANDS")
+        .into_iter()
+        .filter(|(name, _)| *name != "help")
+        .collect();
+    items(&mut page, "COMMANDS", &subcommands);
+    if command.is_empty() {
+        hooks(&mut page);
+        environment(&mut page);
+    }
+
+    let mut see_also: Vec<String> = help
+        .subcommands()
+        .iter()
+        .map(|subcommand| {
+            let mut command = command.to_vec();
+            command.push(subcommand.clone());
+            page_name(&command)
+        })
+        .collect();
+    if let Some((_, parent)) = command.split_last() {
+        see_also.insert(0, page_name(parent));
+    }
+    if !see_also.is_empty() {
+        let see_also: Vec<String> = see_also
+            .iter()
+            .map(|name| format!("\\fB{}\\fR(1)", escape(name)))
+            .collect();
+        let _ = writeln!(page, ".SH SEE ALSO\n{}", see_also.join(",\n"));
+    }
+    Ok(page)
+}
+
+/// Writes the pages of `passage` and all of its subcommands to `dir` and returns their
+/// paths
+pub fn write_all(app: &App, dir: &Path) -> Result<Vec<PathBuf>> {
+    fs::create_dir_all(dir)?;
+    let mut written = vec![];
+    let mut commands = vec![vec![]];
+    while let Some(command) = commands.pop() {
+        let path = dir.join(format!("{}.1", page_name(&command)));
+        fs::write(&path, page(app, &command)?)?;
+        written.push(path);
+        for subcommand in Help::of(app, &command)?.subcommands().into_iter().rev() {
+            let mut command = command.clone();
+            command.push(subcommand);
+            commands.push(command);
+        }
+    }
+    Ok(written)
+}
@@ -0,0 +1,242 @@
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error, Result};
+
+use crate::{Entry, Storage};
+
+/// An entry which was changed differently on both sides, `None` means it didn't exist
+#[derive(Debug, PartialEq, Eq)]
+pub struct Conflict {
+    pub name: String,
+    pub base: Option<Entry>,
+    pub ours: Option<Entry>,
+    pub theirs: Option<Entry>,
+}
+
+/// Which version of a conflicting entry to keep
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Ours,
+    Theirs,
+}
+
+impl FromStr for Side {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ours" => Ok(Self::Ours),
+            "theirs" => Ok(Self::Theirs),
+            _ => Err(anyhow!("expected ours or theirs, got {}", s)),
+        }
+    }
+}
+
+/// The outcome of a three-way merge, conflicting entries are not part of `storage`
+#[derive(Debug)]
+pub struct Merge {
+    pub storage: Storage,
+    pub conflicts: Vec<Conflict>,
+}
+
+/// Merges `ours` and `theirs`, which both started out as `base`, entry by entry.
+///
+/// An entry which only changed on one side takes that change, entries which changed
+/// differently on both sides are returned as conflicts (sorted by name).
+#[must_use]
+pub fn merge(base: &Storage, ours: &Storage, theirs: &Storage) -> Merge {
+    let names: BTreeSet<&String> = base
+        .entries
+        .keys()
+        .chain(ours.entries.keys())
+        .chain(theirs.entries.keys())
+        .collect();
+
+    let mut merged = Merge {
+        storage: Storage::default(),
+        conflicts: vec![],
+    };
+    for name in names {
+        let base = base.entries.get(name);
+        let ours = ours.entries.get(name);
+        let theirs = theirs.entries.get(name);
+        let result = if ours == theirs || theirs == base {
+            ours
+        } else if ours == base {
+            theirs
+        } else {
+            merged.conflicts.push(Conflict {
+                name: name.clone(),
+                base: base.cloned(),
+                ours: ours.cloned(),
+                theirs: theirs.cloned(),
+            });
+            continue;
+        };
+        if let Some(entry) = result {
+            merged.storage.entries.insert(name.clone(), entry.clone());
+        }
+    }
+
+    merged
+}
+
+/// Resolves the conflicts of `merged` with the side `choose` picks for each of them
+pub fn resolve(
+    merged: Merge,
+    mut choose: impl FnMut(&Conflict) -> Result<Side>,
+) -> Result<Storage> {
+    let mut storage = merged.storage;
+    for conflict in merged.conflicts {
+        let entry = match choose(&conflict)? {
+            Side::Ours => conflict.ours,
+            Side::Theirs => conflict.theirs,
+        };
+        if let Some(entry) = entry {
+            storage.entries.insert(conflict.name, entry);
+        }
+    }
+    Ok(storage)
+}
+
+/// Describes what happened to an entry on one side without revealing any secrets,
+/// e.g. "changed password, username"
+fn describe_change(base: Option<&Entry>, entry: Option<&Entry>) -> String {
+    match (base, entry) {
+        (_, None) => "removed".to_string(),
+        (None, Some(_)) => "added".to_string(),
+        (Some(base), Some(entry)) => {
+            let mut fields = vec![];
+            if base.password != entry.password {
+                fields.push("password");
+            }
+            if base.username != entry.username {
+                fields.push("username");
+            }
+            if base.autotype != entry.autotype {
+                fields.push("autotype");
+            }
+            format!("changed {}", fields.join(", "))
+        }
+    }
+}
+
+/// Asks on stdin which side of `conflict` to keep
+pub fn prompt(conflict: &Conflict) -> Result<Side> {
+    println!("Conflict in {}:", conflict.name);
+    println!(
+        "  ours: {}",
+        describe_change(conflict.base.as_ref(), conflict.ours.as_ref())
+    );
+    println!(
+        "  theirs: {}",
+        describe_change(conflict.base.as_ref(), conflict.theirs.as_ref())
+    );
+    loop {
+        print!("Keep (o)urs or (t)heirs? ");
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        if io::stdin().read_line(&mut answer)? == 0 {
+            return Err(anyhow!("unresolved conflict in {}", conflict.name));
+        }
+        match answer.trim() {
+            "o" | "ours" => return Ok(Side::Ours),
+            "t" | "theirs" => return Ok(Side::Theirs),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a storage of entries with just a password, from pairs of name and password
+    fn storage(entries: &[(&str, &str)]) -> Storage {
+        Storage {
+            entries: entries
+                .iter()
+                .map(|(name, password)| ((*name).to_string(), Entry::new(*password)))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_merge_independent_changes() {
+        let base = storage(&[("a", "1"), ("b", "1"), ("c", "1")]);
+        let ours = storage(&[("a", "2"), ("b", "1"), ("c", "1"), ("new", "1")]);
+        let theirs = storage(&[("a", "1"), ("b", "2")]);
+
+        let merged = merge(&base, &ours, &theirs);
+        assert!(merged.conflicts.is_empty());
+        assert_eq!(
+            merged.storage.entries,
+            storage(&[("a", "2"), ("b", "2"), ("new", "1")]).entries
+        );
+    }
+
+    #[test]
+    fn test_merge_same_change() {
+        let base = storage(&[("a", "1")]);
+        let both = storage(&[("a", "2"), ("b", "2")]);
+
+        let merged = merge(&base, &both, &both);
+        assert!(merged.conflicts.is_empty());
+        assert_eq!(merged.storage.entries, both.entries);
+    }
+
+    #[test]
+    fn test_merge_conflicts() {
+        let base = storage(&[("a", "1"), ("b", "1")]);
+        let ours = storage(&[("a", "2"), ("b", "2"), ("c", "2")]);
+        let theirs = storage(&[("a", "3"), ("c", "3")]);
+
+        let merged = merge(&base, &ours, &theirs);
+        assert!(merged.storage.entries.is_empty());
+        let names: Vec<&str> = merged.conflicts.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "b", "c"]);
+        assert_eq!(merged.conflicts[1].theirs, None);
+        assert_eq!(merged.conflicts[2].base, None);
+        assert_eq!(
+            merged.conflicts[2]
+                .ours
+                .as_ref()
+                .map(|e| e.password.as_str()),
+            Some("2")
+        );
+    }
+
+    #[test]
+    fn test_resolve() {
+        let base = storage(&[("a", "1"), ("b", "1"), ("c", "1")]);
+        let ours = storage(&[("a", "2"), ("b", "2"), ("c", "2")]);
+        let theirs = storage(&[("a", "3"), ("c", "3")]);
+
+        let resolved = resolve(merge(&base, &ours, &theirs), |conflict| {
+            Ok(if conflict.name == "a" {
+                Side::Ours
+            } else {
+                Side::Theirs
+            })
+        })
+        .unwrap();
+        assert_eq!(resolved.entries, storage(&[("a", "2"), ("c", "3")]).entries);
+    }
+
+    #[test]
+    fn test_describe_change() {
+        let base = storage(&[("a", "1")]).entries.remove("a").unwrap();
+        let mut changed = base.clone();
+        changed.password = "2".to_string();
+        changed.username = Some("bob".to_string());
+
+        assert_eq!(describe_change(Some(&base), None), "removed");
+        assert_eq!(describe_change(None, Some(&base)), "added");
+        assert_eq!(
+            describe_change(Some(&base), Some(&changed)),
+            "changed password, username"
+        );
+    }
+}
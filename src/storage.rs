@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+use anyhow::anyhow;
+use secrecy::Secret;
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+/// The entries of a password store, this is what the storage file holds
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Storage {
+    #[serde(flatten)]
+    pub entries: HashMap<String, Entry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Entry {
+    pub password: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// Autotype sequence overriding the configured one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub autotype: Option<String>,
+}
+
+impl Entry {
+    /// Creates an entry with just a password
+    pub fn new(password: impl Into<String>) -> Self {
+        Self {
+            password: password.into(),
+            username: None,
+            autotype: None,
+        }
+    }
+}
+
+impl Storage {
+    /// Decrypts the contents of a storage file, an empty file has no entries
+    pub fn decrypt(encrypted: &[u8], passphrase: &Secret<String>) -> Result<Self> {
+        if encrypted.is_empty() {
+            Ok(Self::default())
+        } else {
+            let decrypted = String::from_utf8(decrypt(encrypted, passphrase)?)
+                .map_err(|e| anyhow!("storage isn't valid UTF-8: {}", e))?;
+            Ok(toml::from_str(&decrypted).map_err(anyhow::Error::from)?)
+        }
+    }
+
+    /// Encrypts the entries into the contents of a storage file
+    pub fn encrypt(&self, passphrase: &Secret<String>) -> Result<Vec<u8>> {
+        let bytes = toml::to_vec(self).map_err(anyhow::Error::from)?;
+        encrypt(&bytes, passphrase.clone())
+    }
+}
+
+fn encrypt(plaintext: &[u8], passphrase: Secret<String>) -> Result<Vec<u8>> {
+    let encryptor = age::Encryptor::with_user_passphrase(passphrase);
+
+    let mut encrypted = vec![];
+    let mut writer = encryptor
+        .wrap_output(&mut encrypted)
+        .map_err(anyhow::Error::msg)?;
+    writer.write_all(plaintext)?;
+    writer.finish()?;
+
+    Ok(encrypted)
+}
+
+fn decrypt(encrypted: &[u8], passphrase: &Secret<String>) -> Result<Vec<u8>> {
+    let decryptor = match age::Decryptor::new(encrypted)? {
+        age::Decryptor::Passphrase(d) => d,
+        age::Decryptor::Recipients(..) => unreachable!(),
+    };
+
+    let mut decrypted = vec![];
+    let mut reader = decryptor.decrypt(passphrase, None)?;
+    loop {
+        let bytes = reader.read_to_end(&mut decrypted)?;
+        if bytes == 0 {
+            break;
+        }
+    }
+
+    Ok(decrypted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    #[test]
+    fn test_ok() {
+        let text = b"this is plain";
+        let passphrase = Secret::new("secret".to_string());
+        let encrypted = encrypt(text, passphrase.clone()).unwrap();
+        let decrypted = decrypt(&encrypted, &passphrase).unwrap();
+        assert_eq!(decrypted, text);
+    }
+
+    #[test]
+    fn test_wrong_passphrase() {
+        let encrypted = encrypt(b"[foo]\npassword = 'bar'", Secret::new("right".into())).unwrap();
+        let wrong = Storage::decrypt(&encrypted, &Secret::new("wrong".into())).unwrap_err();
+        assert!(matches!(wrong, Error::WrongPassphrase));
+        let garbage = Storage::decrypt(b"not age", &Secret::new("right".into())).unwrap_err();
+        assert!(!matches!(garbage, Error::WrongPassphrase));
+    }
+
+    #[test]
+    fn test_entry_serialization() {
+        let s: Storage = toml::from_str("[foo] \n password = 'bar'").unwrap();
+        assert_eq!(s.entries.get("foo").unwrap().password, "bar");
+    }
+}
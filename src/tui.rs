@@ -0,0 +1,728 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{self, Stdout};
+use std::panic;
+
+use anyhow::Result;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use passage::config::Config;
+use passage::hooks::{run_hook, Hook, HookEvent};
+use passage::{Entry, Vault};
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph};
+use ratatui::{Frame, Terminal};
+
+use crate::clipboard;
+
+type Term = Terminal<CrosstermBackend<Stdout>>;
+
+const HELP: &str = "/ filter  c copy password  u copy username  a add  e edit  d delete  q quit";
+
+/// Labels of the fields of the entry form, in the order of `Form::fields`
+const FIELDS: [&str; 4] = ["Name", "Username", "Password", "Autotype"];
+
+/// One line of the entry tree, either an entry or a folder (the part of entry names before
+/// a `/`)
+#[derive(Debug, PartialEq, Eq)]
+struct Row {
+    depth: usize,
+    label: String,
+    /// Full name of the entry, `None` for folders
+    entry: Option<String>,
+}
+
+/// Arranges sorted entry names as a tree, `work/aws` becomes the entry `aws` in the folder
+/// `work/`
+fn tree(names: &[&str]) -> Vec<Row> {
+    let mut rows = vec![];
+    let mut previous: &[&str] = &[];
+    let parts: Vec<Vec<&str>> = names.iter().map(|name| name.split('/').collect()).collect();
+    for (name, parts) in names.iter().zip(&parts) {
+        let (label, folders) = parts.split_last().unwrap_or((name, &[]));
+        let common = previous
+            .iter()
+            .zip(folders)
+            .take_while(|(a, b)| a == b)
+            .count();
+        for (depth, folder) in folders.iter().enumerate().skip(common) {
+            rows.push(Row {
+                depth,
+                label: format!("{}/", folder),
+                entry: None,
+            });
+        }
+        rows.push(Row {
+            depth: folders.len(),
+            label: (*label).to_string(),
+            entry: Some((*name).to_string()),
+        });
+        previous = folders;
+    }
+    rows
+}
+
+/// A change of the storage, it's only kept if saving it works
+#[derive(Debug, PartialEq, Eq)]
+enum Change {
+    /// Adds an entry, or replaces `original` with it
+    Save {
+        original: Option<String>,
+        name: String,
+        entry: Entry,
+    },
+    Remove(String),
+}
+
+impl Change {
+    const fn event(&self) -> HookEvent {
+        match self {
+            Self::Save { original: None, .. } => HookEvent::NewEntry,
+            Self::Save { .. } => HookEvent::EditEntry,
+            Self::Remove(_) => HookEvent::RemoveEntry,
+        }
+    }
+
+    fn entries(&self) -> Vec<&str> {
+        match self {
+            Self::Save {
+                original: Some(original),
+                name,
+                ..
+            } if original != name => vec![original, name],
+            Self::Save { name, .. } | Self::Remove(name) => vec![name],
+        }
+    }
+
+    fn apply(&self, vault: &mut Vault) -> passage::Result<()> {
+        match self {
+            Self::Save {
+                original,
+                name,
+                entry,
+            } => {
+                if let Some(original) = original {
+                    vault.remove(original)?;
+                }
+                vault.insert(name, entry.clone())?;
+            }
+            Self::Remove(name) => {
+                vault.remove(name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The form for adding or editing an entry
+#[derive(Debug)]
+struct Form {
+    /// Name of the entry being edited, `None` when adding one
+    original: Option<String>,
+    fields: [String; 4],
+    focus: usize,
+}
+
+impl Form {
+    fn new(original: Option<&str>, entry: Option<&Entry>) -> Self {
+        Self {
+            original: original.map(str::to_string),
+            fields: [
+                original.unwrap_or_default().to_string(),
+                entry.and_then(|e| e.username.clone()).unwrap_or_default(),
+                entry.map(|e| e.password.clone()).unwrap_or_default(),
+                entry.and_then(|e| e.autotype.clone()).unwrap_or_default(),
+            ],
+            focus: 0,
+        }
+    }
+
+    /// Turns the form into a change, fails with a message for the user if a field is
+    /// missing or the name is taken
+    fn submit(&self, vault: &Vault) -> Result<Change, String> {
+        let [name, username, password, autotype] = &self.fields;
+        if name.is_empty() {
+            return Err("The name can't be empty".to_string());
+        }
+        if password.is_empty() {
+            return Err("The password can't be empty".to_string());
+        }
+        let renamed = self.original.as_ref() != Some(name);
+        if renamed && vault.contains(name).unwrap_or(false) {
+            return Err(format!("There is an entry named {} already", name));
+        }
+        let optional = |value: &String| Some(value.clone()).filter(|value| !value.is_empty());
+        Ok(Change::Save {
+            original: self.original.clone(),
+            name: name.clone(),
+            entry: Entry {
+                password: password.clone(),
+                username: optional(username),
+                autotype: optional(autotype),
+            },
+        })
+    }
+}
+
+#[derive(Debug)]
+enum Mode {
+    Browse,
+    Filter,
+    Form(Form),
+    ConfirmRemove(String),
+}
+
+/// State of the terminal interface, the vault stays unlocked for the whole session
+struct App<'a> {
+    config: &'a Config,
+    vault: Vault,
+    filter: String,
+    rows: Vec<Row>,
+    list: ListState,
+    mode: Mode,
+    status: Option<String>,
+    /// Copied secrets this process still has to clear from the clipboard
+    copied: Vec<clipboard::Copied>,
+    quit: bool,
+}
+
+impl<'a> App<'a> {
+    fn new(config: &'a Config, vault: Vault) -> Result<Self> {
+        let mut app = Self {
+            config,
+            vault,
+            filter: String::new(),
+            rows: vec![],
+            list: ListState::default(),
+            mode: Mode::Browse,
+            status: None,
+            copied: vec![],
+            quit: false,
+        };
+        app.refresh(None)?;
+        Ok(app)
+    }
+
+    /// Rebuilds the tree of the entries matching the filter (on the name or username, like
+    /// `passage find`) and selects `keep` if it's still there, the first entry otherwise
+    fn refresh(&mut self, keep: Option<&str>) -> Result<()> {
+        let filter = self.filter.to_lowercase();
+        let entries = &self.vault.storage()?.entries;
+        let names: Vec<&str> = self
+            .vault
+            .list()?
+            .into_iter()
+            .filter(|name| {
+                name.to_lowercase().contains(&filter)
+                    || entries[*name]
+                        .username
+                        .as_ref()
+                        .is_some_and(|username| username.to_lowercase().contains(&filter))
+            })
+            .collect();
+        self.rows = tree(&names);
+        let selected = keep
+            .and_then(|keep| {
+                self.rows
+                    .iter()
+                    .position(|row| row.entry.as_deref() == Some(keep))
+            })
+            .or_else(|| self.rows.iter().position(|row| row.entry.is_some()));
+        self.list.select(selected);
+        Ok(())
+    }
+
+    fn selected(&self) -> Option<&str> {
+        self.list
+            .selected()
+            .and_then(|i| self.rows.get(i))
+            .and_then(|row| row.entry.as_deref())
+    }
+
+    /// Moves the selection to the next (or previous) entry, skipping folders
+    fn step(&mut self, forward: bool) {
+        let Some(current) = self.list.selected() else {
+            return;
+        };
+        let is_entry = |i: &usize| self.rows[*i].entry.is_some();
+        let next = if forward {
+            (current + 1..self.rows.len()).find(is_entry)
+        } else {
+            (0..current).rev().find(is_entry)
+        };
+        if let Some(next) = next {
+            self.list.select(Some(next));
+        }
+    }
+
+    fn copy(&mut self, username: bool) {
+        let Some(name) = self.selected().map(str::to_string) else {
+            return;
+        };
+        let Ok(entry) = self.vault.get(&name) else {
+            return;
+        };
+        let (what, value) = if username {
+            let Some(username) = &entry.username else {
+                self.status = Some(format!("{} has no username", name));
+                return;
+            };
+            ("username", username)
+        } else {
+            ("password", &entry.password)
+        };
+        self.status = Some(
+            match clipboard::copy_quietly(&self.config.clipboard, value, self.config.clip_time) {
+                Ok(copied) => {
+                    let status = format!(
+                        "Copied the {} of {}, clearing in {} seconds",
+                        what, name, copied.clip_time
+                    );
+                    self.copied.retain(clipboard::Copied::is_pending);
+                    self.copied.push(copied);
+                    status
+                }
+                Err(e) => format!("Error: {:#}", e),
+            },
+        );
+    }
+
+    /// Reacts to a key press, returns the change to save if there is one
+    fn handle(&mut self, key: KeyEvent) -> Result<Option<Change>> {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.quit = true;
+            return Ok(None);
+        }
+        self.status = None;
+        match &mut self.mode {
+            Mode::Browse => match key.code {
+                KeyCode::Char('q') => self.quit = true,
+                KeyCode::Esc if self.filter.is_empty() => self.quit = true,
+                KeyCode::Esc => {
+                    let keep = self.selected().map(str::to_string);
+                    self.filter.clear();
+                    self.refresh(keep.as_deref())?;
+                }
+                KeyCode::Down | KeyCode::Char('j') => self.step(true),
+                KeyCode::Up | KeyCode::Char('k') => self.step(false),
+                KeyCode::Char('/') => self.mode = Mode::Filter,
+                KeyCode::Char('c') => self.copy(false),
+                KeyCode::Char('u') => self.copy(true),
+                KeyCode::Char('a') => self.mode = Mode::Form(Form::new(None, None)),
+                KeyCode::Char('e') | KeyCode::Enter => {
+                    if let Some(name) = self.selected() {
+                        let form = Form::new(Some(name), Some(self.vault.get(name)?));
+                        self.mode = Mode::Form(form);
+                    }
+                }
+                KeyCode::Char('d') => {
+                    if let Some(name) = self.selected() {
+                        self.mode = Mode::ConfirmRemove(name.to_string());
+                    }
+                }
+                _ => {}
+            },
+            Mode::Filter => {
+                let keep = self.selected().map(str::to_string);
+                match key.code {
+                    KeyCode::Enter | KeyCode::Down => self.mode = Mode::Browse,
+                    KeyCode::Esc => {
+                        self.filter.clear();
+                        self.mode = Mode::Browse;
+                    }
+                    KeyCode::Backspace => {
+                        self.filter.pop();
+                    }
+                    KeyCode::Char(c) => self.filter.push(c),
+                    _ => {}
+                }
+                self.refresh(keep.as_deref())?;
+            }
+            Mode::Form(form) => match key.code {
+                KeyCode::Esc => self.mode = Mode::Browse,
+                KeyCode::Tab | KeyCode::Down => form.focus = (form.focus + 1) % FIELDS.len(),
+                KeyCode::BackTab | KeyCode::Up => {
+                    form.focus = (form.focus + FIELDS.len() - 1) % FIELDS.len();
+                }
+                KeyCode::Backspace => {
+                    form.fields[form.focus].pop();
+                }
+                KeyCode::Char(c) => form.fields[form.focus].push(c),
+                KeyCode::Enter => match form.submit(&self.vault) {
+                    Ok(change) => {
+                        self.mode = Mode::Browse;
+                        return Ok(Some(change));
+                    }
+                    Err(message) => self.status = Some(message),
+                },
+                _ => {}
+            },
+            Mode::ConfirmRemove(name) => {
+                let change = match key.code {
+                    KeyCode::Char('y' | 'Y') => Some(Change::Remove(name.clone())),
+                    _ => None,
+                };
+                self.mode = Mode::Browse;
+                return Ok(change);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Saves `change` with the save hooks around it, the change is undone if the
+    /// `pre_save` hook or writing the storage fails
+    fn save(&mut self, change: &Change) -> Result<()> {
+        let event = change.event();
+        let entries = change.entries();
+        let backup: HashMap<String, Entry> = self.vault.storage()?.entries.clone();
+        change.apply(&mut self.vault)?;
+        let saved = run_hook(self.config, &Hook::PreSave, &event, &entries)
+            .and_then(|()| Ok(self.vault.save()?));
+        if let Err(e) = saved {
+            self.vault.storage_mut()?.entries = backup;
+            return Err(e);
+        }
+        run_hook(self.config, &Hook::PostSave, &event, &entries)
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status] =
+            Layout::vertical([Constraint::Min(3), Constraint::Length(1)]).areas(frame.area());
+        let [entries, details] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .areas(main);
+
+        let title = match (&self.mode, self.filter.is_empty()) {
+            (Mode::Filter, _) | (_, false) => format!(" Entries /{} ", self.filter),
+            _ => " Entries ".to_string(),
+        };
+        let items: Vec<ListItem> = self
+            .rows
+            .iter()
+            .map(|row| {
+                let style = if row.entry.is_some() {
+                    Style::default()
+                } else {
+                    Style::default().add_modifier(Modifier::BOLD)
+                };
+                ListItem::new(Span::styled(
+                    format!("{}{}", "  ".repeat(row.depth), row.label),
+                    style,
+                ))
+            })
+            .collect();
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(title))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, entries, &mut self.list);
+
+        let mut lines = vec![];
+        if let Some(entry) = self.selected().and_then(|name| self.vault.get(name).ok()) {
+            let field = |label: &str, value: Option<&String>| {
+                Line::from(vec![
+                    Span::styled(
+                        format!("{:<10}", label),
+                        Style::default().add_modifier(Modifier::BOLD),
+                    ),
+                    Span::raw(value.cloned().unwrap_or_default()),
+                ])
+            };
+            lines.push(field("Name", self.selected().map(str::to_string).as_ref()));
+            lines.push(field("Username", entry.username.as_ref()));
+            lines.push(field("Autotype", entry.autotype.as_ref()));
+        }
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(" Details ")),
+            details,
+        );
+
+        let status_line = match (&self.mode, &self.status) {
+            (_, Some(status)) => status.clone(),
+            (Mode::ConfirmRemove(name), None) => format!("Delete {} (y/N)?", name),
+            (Mode::Filter, None) => {
+                "Type to filter, Enter to keep the filter, Esc to clear it".to_string()
+            }
+            (Mode::Form(_), None) => "Tab next field  Enter save  Esc cancel".to_string(),
+            (Mode::Browse, None) => HELP.to_string(),
+        };
+        frame.render_widget(Paragraph::new(status_line), status);
+
+        if let Mode::Form(form) = &self.mode {
+            draw_form(frame, form);
+        }
+    }
+
+    fn run(&mut self, terminal: &mut Term) -> Result<()> {
+        while !self.quit {
+            terminal.draw(|frame| self.draw(frame))?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            if let Some(change) = self.handle(key)? {
+                // hooks may print or ask for input, so they get the normal terminal
+                suspend()?;
+                let result = self.save(&change);
+                if let Err(e) = resume(terminal) {
+                    result?;
+                    return Err(e);
+                }
+                let keep = match &change {
+                    Change::Save { name, .. } => Some(name.clone()),
+                    Change::Remove(_) => self.selected().map(str::to_string),
+                };
+                self.refresh(keep.as_deref())?;
+                self.status = Some(match result {
+                    Ok(()) => format!("Saved {}", change.entries().join(", ")),
+                    Err(e) => format!("Error: {:#}", e),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Terminal coordinates are `u16`, anything larger gets cut off anyway
+fn to_u16(n: usize) -> u16 {
+    u16::try_from(n).unwrap_or(u16::MAX)
+}
+
+fn draw_form(frame: &mut Frame, form: &Form) {
+    let area = frame.area();
+    let width = area.width.saturating_sub(4).min(60);
+    let height = (to_u16(FIELDS.len()) + 2).min(area.height);
+    let popup = Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    );
+    let title = form.original.as_ref().map_or_else(
+        || " New entry ".to_string(),
+        |name| format!(" Edit {} ", name),
+    );
+    let lines: Vec<Line> = FIELDS
+        .iter()
+        .zip(&form.fields)
+        .enumerate()
+        .map(|(i, (label, value))| {
+            let value = if *label == "Password" {
+                "*".repeat(value.chars().count())
+            } else {
+                value.clone()
+            };
+            let style = if i == form.focus {
+                Style::default().add_modifier(Modifier::BOLD)
+            } else {
+                Style::default()
+            };
+            Line::from(vec![
+                Span::styled(format!("{:<10}", label), style),
+                Span::raw(value),
+            ])
+        })
+        .collect();
+    frame.render_widget(Clear, popup);
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title)),
+        popup,
+    );
+    // after the border and the label
+    let column = to_u16(form.fields[form.focus].chars().count()).saturating_add(11);
+    frame.set_cursor_position((
+        popup
+            .x
+            .saturating_add(column)
+            .min(popup.right().saturating_sub(2)),
+        popup.y + 1 + to_u16(form.focus),
+    ));
+}
+
+fn suspend() -> Result<()> {
+    disable_raw_mode()?;
+    execute!(io::stdout(), LeaveAlternateScreen)?;
+    Ok(())
+}
+
+fn resume(terminal: &mut Term) -> Result<()> {
+    enable_raw_mode()?;
+    execute!(io::stdout(), EnterAlternateScreen)?;
+    terminal.clear()?;
+    Ok(())
+}
+
+/// Puts the terminal back to normal when dropped, so it isn't left in raw mode when the
+/// interface returns early with an error
+struct Restore;
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        let _ = suspend();
+    }
+}
+
+/// Browses and edits the entries of the unlocked `vault` in a full-screen terminal
+/// interface until the user quits
+pub fn run(config: &Config, vault: Vault) -> Result<()> {
+    let mut app = App::new(config, vault)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+
+    // the panic message is printed before `Restore` gets dropped, it would end up on the
+    // alternate screen and vanish
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let _ = suspend();
+        default_hook(info);
+    }));
+    let restore = Restore;
+    let result = resume(&mut terminal).and_then(|()| app.run(&mut terminal));
+    drop(restore);
+    // back to the default hook, `passage` sets no other
+    drop(panic::take_hook());
+
+    if app.copied.iter().any(clipboard::Copied::is_pending) {
+        println!("Waiting for the clipboard to be cleared");
+    }
+    for copied in app.copied {
+        copied.wait();
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use passage::backend::MemoryBackend;
+    use passage::Secret;
+
+    fn vault(entries: &[(&str, Option<&str>)]) -> Vault {
+        let mut vault = Vault::new(Box::new(MemoryBackend::new()));
+        vault.init(Secret::new("passphrase".to_string())).unwrap();
+        for (name, username) in entries {
+            let mut entry = Entry::new("secret");
+            entry.username = username.map(str::to_string);
+            vault.insert(name, entry).unwrap();
+        }
+        vault
+    }
+
+    fn press(app: &mut App, keys: &str) -> Option<Change> {
+        let mut change = None;
+        for c in keys.chars() {
+            let code = match c {
+                '\n' => KeyCode::Enter,
+                '\t' => KeyCode::Tab,
+                '\x1b' => KeyCode::Esc,
+                '\x08' => KeyCode::Backspace,
+                c => KeyCode::Char(c),
+            };
+            change = app.handle(KeyEvent::new(code, KeyModifiers::NONE)).unwrap();
+        }
+        change
+    }
+
+    #[test]
+    fn test_tree() {
+        let row = |depth, label: &str, entry: Option<&str>| Row {
+            depth,
+            label: label.to_string(),
+            entry: entry.map(str::to_string),
+        };
+        assert_eq!(
+            tree(&[
+                "github",
+                "work/aws",
+                "work/db/prod",
+                "work/db/test",
+                "work/mail"
+            ]),
+            vec![
+                row(0, "github", Some("github")),
+                row(0, "work/", None),
+                row(1, "aws", Some("work/aws")),
+                row(1, "db/", None),
+                row(2, "prod", Some("work/db/prod")),
+                row(2, "test", Some("work/db/test")),
+                row(1, "mail", Some("work/mail")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_browse() {
+        let config = Config::default();
+        let mut app = App::new(
+            &config,
+            vault(&[
+                ("github", Some("chris")),
+                ("work/aws", None),
+                ("work/mail", None),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(app.selected(), Some("github"));
+        press(&mut app, "jj");
+        assert_eq!(app.selected(), Some("work/mail"));
+        press(&mut app, "kk");
+        assert_eq!(app.selected(), Some("github"));
+
+        // the filter matches usernames as well
+        press(&mut app, "/CHR");
+        assert_eq!(app.rows.len(), 1);
+        press(&mut app, "\x08\x08\x08aws\n");
+        assert_eq!(app.selected(), Some("work/aws"));
+        assert_eq!(app.filter, "aws");
+        press(&mut app, "\x1b");
+        assert_eq!(app.rows.len(), 4);
+        assert_eq!(app.selected(), Some("work/aws"));
+        assert!(!app.quit);
+        press(&mut app, "q");
+        assert!(app.quit);
+    }
+
+    #[test]
+    fn test_changes() {
+        let mut config = Config::default();
+        config.hooks.enabled = false;
+        let mut app = App::new(&config, vault(&[("github", None), ("work/aws", None)])).unwrap();
+
+        assert_eq!(press(&mut app, "agithub\t\tpw\n"), None);
+        assert_eq!(
+            app.status.as_deref(),
+            Some("There is an entry named github already")
+        );
+        assert_eq!(
+            press(&mut app, "\t\t\x08\x08\x08\x08\x08\x08gitlab\n"),
+            Some(Change::Save {
+                original: None,
+                name: "gitlab".to_string(),
+                entry: Entry::new("pw"),
+            })
+        );
+
+        press(&mut app, "j");
+        let change = press(&mut app, "e\x08\x08\x08azure\tchris\n").unwrap();
+        assert_eq!(change.entries(), ["work/aws", "work/azure"]);
+        app.save(&change).unwrap();
+        assert!(app.vault.get("work/aws").is_err());
+        assert_eq!(
+            app.vault.get("work/azure").unwrap().username.as_deref(),
+            Some("chris")
+        );
+        app.refresh(Some("github")).unwrap();
+
+        assert_eq!(press(&mut app, "dn"), None);
+        assert_eq!(
+            press(&mut app, "dy"),
+            Some(Change::Remove("github".to_string()))
+        );
+    }
+}
@@ -0,0 +1,284 @@
+use std::fs;
+use std::path::Path;
+
+use secrecy::Secret;
+
+use crate::backend::{self, StorageBackend, Version};
+use crate::config::{BackendKind, Config};
+use crate::{storage_dir, Entry, Error, Result, Storage};
+
+const ID_FILE: &str = ".vault_id";
+
+/// A password store on top of a `StorageBackend`.
+///
+/// It starts out locked, its entries can be read and changed once it's unlocked with the
+/// passphrase. Changes are only written by `save`, which fails if someone else saved in
+/// the meantime.
+#[derive(Debug)]
+pub struct Vault {
+    backend: Box<dyn StorageBackend>,
+    unlocked: Option<Unlocked>,
+}
+
+#[derive(Debug)]
+struct Unlocked {
+    passphrase: Secret<String>,
+    storage: Storage,
+    /// Version of the storage the entries were read from, `None` for a new storage
+    version: Option<Version>,
+}
+
+impl Vault {
+    /// Creates a locked vault on top of `backend`
+    #[must_use]
+    pub fn new(backend: Box<dyn StorageBackend>) -> Self {
+        Self {
+            backend,
+            unlocked: None,
+        }
+    }
+
+    /// Opens the password store in the storage folder of `config`
+    pub fn open(config: &Config) -> Result<Self> {
+        let vault = Self::new(backend::open(config)?);
+        if !vault.backend.exists()? {
+            return Err(Error::NotInitialized);
+        }
+        Ok(vault)
+    }
+
+    /// Creates a new password store in the storage folder of `config` encrypted with
+    /// `passphrase`, the storage is written on the first `save`
+    pub fn create(config: &Config, passphrase: Secret<String>) -> Result<Self> {
+        let mut vault = Self::new(backend::open(config)?);
+        vault.init(passphrase)?;
+        Ok(vault)
+    }
+
+    /// Starts a new password store without any entries, encrypted with `passphrase`
+    pub fn init(&mut self, passphrase: Secret<String>) -> Result<()> {
+        if self.backend.exists()? {
+            return Err(Error::AlreadyInitialized);
+        }
+        self.unlocked = Some(Unlocked {
+            passphrase,
+            storage: Storage::default(),
+            version: None,
+        });
+        Ok(())
+    }
+
+    #[must_use]
+    pub const fn is_unlocked(&self) -> bool {
+        self.unlocked.is_some()
+    }
+
+    /// Decrypts the entries, fails with `Error::WrongPassphrase` if the storage was
+    /// encrypted with another passphrase
+    pub fn unlock(&mut self, passphrase: Secret<String>) -> Result<()> {
+        let (encrypted, version) = self.backend.read()?.ok_or(Error::NotInitialized)?;
+        let storage = Storage::decrypt(&encrypted, &passphrase)?;
+        self.unlocked = Some(Unlocked {
+            passphrase,
+            storage,
+            version: Some(version),
+        });
+        Ok(())
+    }
+
+    fn unlocked(&self) -> Result<&Unlocked> {
+        self.unlocked.as_ref().ok_or(Error::Locked)
+    }
+
+    fn unlocked_mut(&mut self) -> Result<&mut Unlocked> {
+        self.unlocked.as_mut().ok_or(Error::Locked)
+    }
+
+    pub fn storage(&self) -> Result<&Storage> {
+        Ok(&self.unlocked()?.storage)
+    }
+
+    pub fn storage_mut(&mut self) -> Result<&mut Storage> {
+        Ok(&mut self.unlocked_mut()?.storage)
+    }
+
+    /// Returns the names of all entries, sorted
+    pub fn list(&self) -> Result<Vec<&str>> {
+        let mut names: Vec<&str> = self.storage()?.entries.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        Ok(names)
+    }
+
+    pub fn contains(&self, name: &str) -> Result<bool> {
+        Ok(self.storage()?.entries.contains_key(name))
+    }
+
+    pub fn get(&self, name: &str) -> Result<&Entry> {
+        self.storage()?
+            .entries
+            .get(name)
+            .ok_or_else(|| Error::EntryNotFound(name.to_string()))
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Result<&mut Entry> {
+        self.storage_mut()?
+            .entries
+            .get_mut(name)
+            .ok_or_else(|| Error::EntryNotFound(name.to_string()))
+    }
+
+    /// Adds an entry, returns the entry it replaced if there already was one named `name`
+    pub fn insert(&mut self, name: &str, entry: Entry) -> Result<Option<Entry>> {
+        Ok(self.storage_mut()?.entries.insert(name.to_string(), entry))
+    }
+
+    /// Removes an entry and returns it
+    pub fn remove(&mut self, name: &str) -> Result<Entry> {
+        self.storage_mut()?
+            .entries
+            .remove(name)
+            .ok_or_else(|| Error::EntryNotFound(name.to_string()))
+    }
+
+    /// Changes the passphrase the storage gets encrypted with on the next `save`
+    pub fn set_passphrase(&mut self, passphrase: Secret<String>) -> Result<()> {
+        self.unlocked_mut()?.passphrase = passphrase;
+        Ok(())
+    }
+
+    /// Decrypts another version of the storage file (e.g. one from git) with the passphrase
+    /// of this vault
+    pub fn decrypt(&self, encrypted: &[u8]) -> Result<Storage> {
+        Storage::decrypt(encrypted, &self.unlocked()?.passphrase)
+    }
+
+    /// Encrypts the entries and writes them to the backend, fails with `Error::Conflict` if
+    /// the storage changed since it was read
+    pub fn save(&mut self) -> Result<()> {
+        let unlocked = self.unlocked.as_mut().ok_or(Error::Locked)?;
+        let encrypted = unlocked.storage.encrypt(&unlocked.passphrase)?;
+        let _lock = self.backend.lock()?;
+        let version = self.backend.write(&encrypted, unlocked.version.as_ref())?;
+        unlocked.version = Some(version);
+        Ok(())
+    }
+}
+
+/// Returns the identifier of the storage folder, if it has one yet
+pub fn read_id(config: &Config) -> Result<Option<String>> {
+    let path = Path::new(&storage_dir(config)?).join(ID_FILE);
+    Ok(fs::read_to_string(path)
+        .ok()
+        .map(|id| id.trim().to_string()))
+}
+
+/// Returns the identifier of the storage folder, which keeps the keyring entries of
+/// different storage folders apart. It gets created on first use, along with the storage
+/// folder if the storage file is kept somewhere else.
+pub fn id(config: &Config) -> Result<String> {
+    if let Some(id) = read_id(config)? {
+        return Ok(id);
+    }
+    let dir = storage_dir(config)?;
+    if fs::metadata(&dir).is_err() {
+        if config.storage_backend() == BackendKind::File {
+            return Err(Error::NotInitialized);
+        }
+        fs::create_dir_all(&dir)?;
+    }
+    let id = uuid::Uuid::new_v4().to_string();
+    fs::write(Path::new(&dir).join(ID_FILE), format!("{}\n", id))?;
+    Ok(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+
+    fn config(dir: &Path) -> Config {
+        Config {
+            storage_folder: Some(dir.display().to_string()),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn test_vault() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config(&dir.path().join("store"));
+        let passphrase = || Secret::new("secret".to_string());
+
+        assert!(matches!(Vault::open(&config), Err(Error::NotInitialized)));
+        let mut vault = Vault::create(&config, passphrase()).unwrap();
+        vault.insert("github", Entry::new("1")).unwrap();
+        vault.insert("aws", Entry::new("2")).unwrap();
+        vault.save().unwrap();
+        assert!(matches!(
+            Vault::create(&config, passphrase()),
+            Err(Error::AlreadyInitialized)
+        ));
+
+        let mut vault = Vault::open(&config).unwrap();
+        assert!(matches!(vault.list(), Err(Error::Locked)));
+        assert!(matches!(
+            vault.unlock(Secret::new("wrong".to_string())),
+            Err(Error::WrongPassphrase)
+        ));
+        vault.unlock(passphrase()).unwrap();
+        assert_eq!(vault.list().unwrap(), vec!["aws", "github"]);
+        assert_eq!(vault.get("github").unwrap().password, "1");
+        assert_eq!(vault.remove("aws").unwrap().password, "2");
+        assert!(matches!(vault.get("aws"), Err(Error::EntryNotFound(_))));
+        assert_eq!(
+            vault.insert("github", Entry::new("3")).unwrap(),
+            Some(Entry::new("1"))
+        );
+    }
+
+    #[test]
+    fn test_concurrent_save() {
+        let backend = MemoryBackend::new();
+        let passphrase = || Secret::new("secret".to_string());
+        let mut vault = Vault::new(Box::new(backend.clone()));
+        vault.init(passphrase()).unwrap();
+        vault.save().unwrap();
+
+        let mut first = Vault::new(Box::new(backend.clone()));
+        first.unlock(passphrase()).unwrap();
+        let mut second = Vault::new(Box::new(backend));
+        second.unlock(passphrase()).unwrap();
+
+        first.insert("a", Entry::new("1")).unwrap();
+        first.save().unwrap();
+        first.insert("b", Entry::new("2")).unwrap();
+        first.save().unwrap();
+        second.insert("c", Entry::new("3")).unwrap();
+        assert!(matches!(second.save(), Err(Error::Conflict)));
+
+        second.unlock(passphrase()).unwrap();
+        assert_eq!(second.list().unwrap(), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = |folder: &str| config(&dir.path().join(folder));
+        fs::create_dir(dir.path().join("a")).unwrap();
+        fs::create_dir(dir.path().join("b")).unwrap();
+
+        assert_eq!(read_id(&config("a")).unwrap(), None);
+        let id = id(&config("a")).unwrap();
+        assert_eq!(super::id(&config("a")).unwrap(), id);
+        assert_eq!(read_id(&config("a")).unwrap(), Some(id.clone()));
+        assert_ne!(super::id(&config("b")).unwrap(), id);
+        assert!(super::id(&config("missing")).is_err());
+
+        let remote = Config {
+            backend: BackendKind::S3,
+            ..config("remote")
+        };
+        assert!(super::id(&remote).is_ok());
+        assert!(dir.path().join("remote").join(ID_FILE).exists());
+    }
+}
@@ -1,8 +1,35 @@
+use std::path::Path;
+
 use assert_cmd::Command;
 use predicates::prelude::*;
 
+/// Runs passage without the config file of the user running the tests, tests which need a
+/// config file point `PASSAGE_CONFIG` somewhere else
 fn passage() -> Command {
-    Command::cargo_bin("passage").unwrap()
+    let mut cmd = Command::cargo_bin("passage").unwrap();
+    cmd.env(
+        "PASSAGE_CONFIG",
+        Path::new(env!("CARGO_TARGET_TMPDIR")).join("no-config.toml"),
+    )
+    .env_remove("PASSAGE_VAULT");
+    cmd
+}
+
+/// Runs passage on the storage folder `dir`, without the keyring
+fn passage_in(dir: &Path) -> Command {
+    let mut cmd = passage();
+    cmd.env("PASSAGE_STORAGE_FOLDER", dir).arg("--no-keyring");
+    cmd
+}
+
+/// Runs passage with the config file `config` and without the keyring, the storage folder
+/// comes from the config file
+fn passage_with_config(config: &Path) -> Command {
+    let mut cmd = passage();
+    cmd.env("PASSAGE_CONFIG", config)
+        .env_remove("PASSAGE_STORAGE_FOLDER")
+        .arg("--no-keyring");
+    cmd
 }
 
 fn tempdir() -> tempfile::TempDir {
@@ -242,7 +269,7 @@ fn fail_list_no_init() {
         .arg("--no-keyring")
         .arg("list")
         .assert()
-        .failure()
+        .code(3)
         .stderr(predicate::str::starts_with(
             "Error: storage not initialized, run `passage init`",
         ));
@@ -257,7 +284,7 @@ fn fail_show_no_init() {
         .arg("show")
         .arg("foo")
         .assert()
-        .failure()
+        .code(3)
         .stderr(predicate::str::starts_with(
             "Error: storage not initialized, run `passage init`",
         ));
@@ -271,7 +298,7 @@ fn fail_new_no_init() {
         .arg("--no-keyring")
         .arg("new")
         .assert()
-        .failure()
+        .code(3)
         .stderr(predicate::str::starts_with(
             "Error: storage not initialized, run `passage init`",
         ));
@@ -298,7 +325,7 @@ fn fail_edit_no_entry() {
         .arg("404")
         .write_stdin(format!("{}\n", passphrase))
         .assert()
-        .failure()
+        .code(6)
         .stdout("Enter passphrase: ")
         .stderr("Error: entry not found: 404\n");
 }
@@ -324,7 +351,2214 @@ fn fail_remove_no_entry() {
         .arg("no-entry")
         .write_stdin(format!("{}\n", passphrase))
         .assert()
-        .failure()
+        .code(6)
         .stdout("Enter passphrase: ")
         .stderr("Error: entry not found: no-entry\n");
 }
+
+/// Parses what a command printed to stdout as JSON
+fn json_stdout(assert: assert_cmd::assert::Assert) -> serde_json::Value {
+    serde_json::from_slice(&assert.get_output().stdout).unwrap()
+}
+
+#[test]
+fn json_output() {
+    let dir = tempdir();
+    let passage_json = || {
+        let mut cmd = passage_in(dir.path());
+        cmd.args(["--output", "json"]);
+        cmd
+    };
+
+    assert_eq!(
+        json_stdout(passage_json().args(["keyring", "check"]).assert().success()),
+        serde_json::json!({ "storages": [{ "vault": null, "passphrase_stored": false }] })
+    );
+    passage_json()
+        .arg("init")
+        .write_stdin("p\n")
+        .assert()
+        .success();
+    passage_json()
+        .args(["new", "--username", "chris"])
+        .write_stdin("p\ngithub\nhunter2\n")
+        .assert()
+        .success();
+
+    let listed = passage_json()
+        .arg("list")
+        .write_stdin("p\n")
+        .assert()
+        .success()
+        .stderr("Enter passphrase: ");
+    assert_eq!(
+        json_stdout(listed),
+        serde_json::json!({ "entries": ["github"] })
+    );
+    // the option can also follow the command
+    assert_eq!(
+        json_stdout(
+            passage_in(dir.path())
+                .args(["list", "--output", "json"])
+                .write_stdin("p\n")
+                .assert()
+                .success()
+        ),
+        serde_json::json!({ "entries": ["github"] })
+    );
+    assert_eq!(
+        json_stdout(
+            passage_json()
+                .args(["show", "github"])
+                .write_stdin("p\n")
+                .assert()
+                .success()
+        ),
+        serde_json::json!({ "name": "github", "username": "chris", "autotype": null })
+    );
+    assert_eq!(
+        json_stdout(
+            passage_json()
+                .args(["show", "--reveal", "github"])
+                .write_stdin("p\n")
+                .assert()
+                .success()
+        ),
+        serde_json::json!({
+            "name": "github",
+            "username": "chris",
+            "autotype": null,
+            "password": "hunter2",
+        })
+    );
+    assert_eq!(
+        json_stdout(
+            passage_json()
+                .args(["find", "CHR"])
+                .write_stdin("p\n")
+                .assert()
+                .success()
+        ),
+        serde_json::json!({ "matches": [{ "vault": null, "entry": "github" }] })
+    );
+    assert_eq!(
+        json_stdout(passage_json().arg("info").assert().success()),
+        serde_json::json!({
+            "vault": null,
+            "storage": {
+                "backend": "file",
+                "exists": true,
+                "path": dir.path().join("entries.toml.age"),
+            },
+            "hooks": {
+                "directory": dir.path().join("hooks"),
+                "exists": false,
+                "scripts": { "pre_load": [], "post_load": [], "pre_save": [], "post_save": [] },
+            },
+        })
+    );
+
+    // errors are JSON as well
+    passage_json()
+        .args(["show", "missing"])
+        .write_stdin("p\n")
+        .assert()
+        .code(6)
+        .stdout("")
+        .stderr(
+            "Enter passphrase: {\"error\":{\"exit_code\":6,\"kind\":\"entry_not_found\",\
+             \"message\":\"entry not found: missing\"}}\n",
+        );
+
+    // hooks print to stderr, so stdout stays a JSON document
+    #[cfg(unix)]
+    {
+        std::fs::create_dir(dir.path().join("hooks")).unwrap();
+        write_script(
+            &dir.path().join("hooks").join("pre_load"),
+            "#!/bin/sh\necho loading\n",
+        );
+        let listed = passage_json()
+            .arg("list")
+            .write_stdin("p\n")
+            .assert()
+            .success()
+            .stderr("Running pre_load hook\npre_load: loading\nEnter passphrase: ");
+        assert_eq!(
+            json_stdout(listed),
+            serde_json::json!({ "entries": ["github"] })
+        );
+    }
+}
+
+#[test]
+fn fail_exit_codes() {
+    let dir = tempdir();
+    passage_in(dir.path())
+        .args(["--json", "list"])
+        .assert()
+        .code(3)
+        .stderr(
+            "{\"error\":{\"exit_code\":3,\"kind\":\"not_initialized\",\
+             \"message\":\"storage not initialized, run `passage init`\"}}\n",
+        );
+    passage_in(dir.path())
+        .arg("init")
+        .write_stdin("right\n")
+        .assert()
+        .success();
+    passage_in(dir.path()).arg("init").assert().success();
+    passage_in(dir.path())
+        .arg("list")
+        .write_stdin("wrong\n")
+        .assert()
+        .code(5)
+        .stderr("Error: wrong passphrase\n");
+    passage_in(dir.path())
+        .args(["show", "--json", "missing"])
+        .write_stdin("right\n")
+        .assert()
+        .code(6)
+        .stderr(
+            "{\"error\":{\"exit_code\":6,\"kind\":\"entry_not_found\",\
+             \"message\":\"entry not found: missing\"}}\n",
+        );
+    passage_in(dir.path())
+        .args(["show", "--no-such-flag"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn config_set_get_list() {
+    let dir = tempdir();
+    let config = dir.path().join("config.toml");
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .args(["config", "get", "clip_time"])
+        .assert()
+        .success()
+        .stdout("10\n");
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .args(["config", "set", "clip_time", "5"])
+        .assert()
+        .success();
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .args(["config", "set", "hooks.enabled", "false"])
+        .assert()
+        .success();
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .args(["config", "get", "clip_time"])
+        .assert()
+        .success()
+        .stdout("5\n");
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .args(["config", "list"])
+        .assert()
+        .success()
+        .stdout(concat!(
+            "keyring = true\n",
+            "clip_time = 5\n",
+            "show_on = \"clipboard\"\n",
+            "clipboard.backend = \"auto\"\n",
+            "clipboard.primary = false\n",
+            "autotype.backend = \"auto\"\n",
+            "hooks.enabled = false\n",
+            "hooks.timeout = 60\n",
+            "hooks.pre_load.on_failure = \"fatal\"\n",
+            "hooks.post_load.on_failure = \"fatal\"\n",
+            "hooks.pre_save.on_failure = \"fatal\"\n",
+            "hooks.post_save.on_failure = \"fatal\"\n",
+            "backend = \"file\"\n",
+            "s3.region = \"us-east-1\"\n",
+            "s3.key = \"entries.toml.age\"\n",
+        ));
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .args(["config", "set", "clip_time", "soon"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::starts_with(
+            "Error: invalid value for clip_time",
+        ));
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .args(["config", "set", "storage_folder", "123"])
+        .assert()
+        .success();
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .args(["config", "get", "storage_folder"])
+        .assert()
+        .success()
+        .stdout("123\n");
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .args(["config", "get", "colour"])
+        .assert()
+        .code(1)
+        .stderr("Error: unknown config key: colour\n");
+}
+
+#[test]
+fn config_repair() {
+    let dir = tempdir();
+    let config = dir.path().join("config.toml");
+    std::fs::write(&config, "clip_time = \"soon\"\n").unwrap();
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("list")
+        .assert()
+        .code(1)
+        .stderr(predicate::str::starts_with("Error: invalid configuration"));
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .env("PASSAGE_KEYRING", "maybe")
+        .args(["config", "get", "clip_time"])
+        .assert()
+        .success()
+        .stdout("soon\n");
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .env("PASSAGE_KEYRING", "maybe")
+        .args(["config", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\nclip_time = \"soon\"\n"));
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .env("PASSAGE_KEYRING", "maybe")
+        .args(["config", "set", "keyring", "false"])
+        .assert()
+        .success();
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .args(["config", "set", "clip_time", "5"])
+        .assert()
+        .success();
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("list")
+        .assert()
+        .code(3);
+}
+
+#[test]
+fn config_precedence() {
+    let dir = tempdir();
+    let config = dir.path().join("config.toml");
+    let config_storage = dir.path().join("from_config");
+    let env_storage = dir.path().join("from_env");
+    let passphrase = "configured";
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .args(["config", "set", "storage_folder"])
+        .arg(&config_storage)
+        .assert()
+        .success();
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .args(["config", "set", "keyring", "false"])
+        .assert()
+        .success();
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .args(["config", "set", "show_on", "screen"])
+        .assert()
+        .success();
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .env_remove("PASSAGE_STORAGE_FOLDER")
+        .arg("info")
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "Storage file doesn't exist yet",
+        ));
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .env_remove("PASSAGE_STORAGE_FOLDER")
+        .arg("init")
+        .write_stdin(format!("{}\n", passphrase))
+        .assert()
+        .success();
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .env_remove("PASSAGE_STORAGE_FOLDER")
+        .arg("info")
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(format!(
+            "Storage file: {}/entries.toml.age\n",
+            config_storage.to_str().unwrap()
+        )));
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .env("PASSAGE_STORAGE_FOLDER", &env_storage)
+        .arg("info")
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "Storage file doesn't exist yet",
+        ));
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .env_remove("PASSAGE_STORAGE_FOLDER")
+        .arg("new")
+        .write_stdin(format!("{}\nentry\npassword", passphrase))
+        .assert()
+        .success();
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .env_remove("PASSAGE_STORAGE_FOLDER")
+        .args(["show", "entry"])
+        .write_stdin(format!("{}\n", passphrase))
+        .assert()
+        .stdout("Enter passphrase: password\n")
+        .success();
+}
+
+#[cfg(unix)]
+fn write_script(path: &Path, contents: &str) {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::write(path, contents).unwrap();
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn clipboard_command_backend() {
+    let dir = tempdir();
+    let config = dir.path().join("config.toml");
+    let clipboard = dir.path().join("clipboard");
+    let copy = dir.path().join("copy");
+    let passphrase = "clip";
+
+    write_script(
+        &copy,
+        &format!("#!/bin/sh\n(cat; echo) >> {}\n", clipboard.display()),
+    );
+    std::fs::write(
+        &config,
+        format!(
+            "[clipboard]\nbackend = \"command\"\ncopy_command = \"{}\"\n",
+            copy.display()
+        ),
+    )
+    .unwrap();
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .arg("init")
+        .write_stdin(format!("{}\n", passphrase))
+        .assert()
+        .success();
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .arg("new")
+        .write_stdin(format!("{}\nentry\npassword", passphrase))
+        .assert()
+        .success();
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .args(["show", "--clip-time", "0", "entry"])
+        .write_stdin(format!("{}\n", passphrase))
+        .assert()
+        .success();
+
+    // on Linux the clipboard also gets restored afterwards, see
+    // clipboard_restore_previous_contents
+    assert!(std::fs::read_to_string(&clipboard)
+        .unwrap()
+        .starts_with("password\n"));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn clipboard_copy_failure() {
+    let dir = tempdir();
+    let config = dir.path().join("config.toml");
+    std::fs::write(
+        &config,
+        "[clipboard]\nbackend = \"command\"\ncopy_command = \"false\"\n",
+    )
+    .unwrap();
+    passage_with_config(&config)
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("init")
+        .write_stdin("clip\n")
+        .assert()
+        .success();
+    passage_with_config(&config)
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("new")
+        .write_stdin("clip\nentry\npassword")
+        .assert()
+        .success();
+
+    // the error of the forked process copying the password is reported by passage itself
+    passage_with_config(&config)
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .args(["show", "entry"])
+        .write_stdin("clip\n")
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("Copied to clipboard").not())
+        .stderr("Error: false failed to copy to clipboard\n");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn clipboard_missing() {
+    let dir = tempdir();
+    let passphrase = "clip";
+
+    passage()
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .arg("init")
+        .write_stdin(format!("{}\n", passphrase))
+        .assert()
+        .success();
+
+    passage()
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .arg("new")
+        .write_stdin(format!("{}\nentry\npassword", passphrase))
+        .assert()
+        .success();
+
+    passage()
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .env_remove("WAYLAND_DISPLAY")
+        .env_remove("DISPLAY")
+        .env_remove("SSH_TTY")
+        .env_remove("SSH_CONNECTION")
+        .arg("--no-keyring")
+        .args(["show", "entry"])
+        .write_stdin(format!("{}\n", passphrase))
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "set clipboard.backend = \"osc52\" to use the clipboard of the terminal",
+        ));
+}
+
+// the previous contents only get restored on Linux, where passage forks to keep the clipboard
+#[cfg(target_os = "linux")]
+#[test]
+fn clipboard_restore_previous_contents() {
+    let dir = tempdir();
+    let config = dir.path().join("config.toml");
+    let clipboard = dir.path().join("clipboard");
+    let history = dir.path().join("history");
+    let copy = dir.path().join("copy");
+    let paste = dir.path().join("paste");
+    let passphrase = "clip";
+
+    write_script(
+        &copy,
+        &format!(
+            "#!/bin/sh\ncat > {clipboard}\n(cat {clipboard}; echo) >> {history}\n",
+            clipboard = clipboard.display(),
+            history = history.display()
+        ),
+    );
+    write_script(&paste, &format!("#!/bin/sh\ncat {}\n", clipboard.display()));
+    std::fs::write(&clipboard, "previous").unwrap();
+    std::fs::write(
+        &config,
+        format!(
+            "[clipboard]\nbackend = \"command\"\ncopy_command = \"{}\"\npaste_command = \"{}\"\n",
+            copy.display(),
+            paste.display()
+        ),
+    )
+    .unwrap();
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .arg("init")
+        .write_stdin(format!("{}\n", passphrase))
+        .assert()
+        .success();
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .arg("new")
+        .write_stdin(format!("{}\nentry\npassword", passphrase))
+        .assert()
+        .success();
+
+    // the child restoring the clipboard keeps stdout open, so this returns once it's done
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .args(["show", "--clip-time", "1", "entry"])
+        .write_stdin(format!("{}\n", passphrase))
+        .assert()
+        .stdout("Enter passphrase: Copied to clipboard, clearing in 1 seconds\n")
+        .success();
+
+    assert_eq!(
+        std::fs::read_to_string(&history).unwrap(),
+        "password\nprevious\n"
+    );
+    assert_eq!(std::fs::read_to_string(&clipboard).unwrap(), "previous");
+}
+
+#[cfg(unix)]
+#[test]
+fn autotype_entry() {
+    let dir = tempdir();
+    let bin = dir.path().join("bin");
+    let log = dir.path().join("typed");
+    let passphrase = "typist";
+
+    std::fs::create_dir(&bin).unwrap();
+    write_script(
+        &bin.join("xdotool"),
+        &format!(
+            "#!/bin/sh\necho \"$@\" >> {log}\nif [ \"$1\" = type ]; then cat >> {log}; echo >> {log}; fi\n",
+            log = log.display()
+        ),
+    );
+    let path = format!(
+        "{}:{}",
+        bin.display(),
+        std::env::var("PATH").unwrap_or_default()
+    );
+
+    passage()
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .arg("init")
+        .write_stdin(format!("{}\n", passphrase))
+        .assert()
+        .success();
+
+    passage()
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .args(["new", "--username", "bob"])
+        .write_stdin(format!("{}\nlogin\nhunter2", passphrase))
+        .assert()
+        .stdout("Passphrase: New entry: Password for login: ")
+        .success();
+
+    passage()
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .env("PASSAGE_AUTOTYPE_BACKEND", "xdotool")
+        .env("PATH", &path)
+        .arg("--no-keyring")
+        .args(["type", "login"])
+        .write_stdin(format!("{}\n", passphrase))
+        .assert()
+        .success();
+
+    assert_eq!(
+        std::fs::read_to_string(&log).unwrap(),
+        "type --clearmodifiers --file -\nbob\nkey --clearmodifiers Tab\n\
+         type --clearmodifiers --file -\nhunter2\nkey --clearmodifiers Return\n"
+    );
+    std::fs::remove_file(&log).unwrap();
+
+    passage()
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .args(["edit", "login", "--autotype", "{password}"])
+        .write_stdin(format!("{}\n", passphrase))
+        .assert()
+        .stdout("Enter passphrase: ")
+        .success();
+
+    passage()
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .env("PASSAGE_AUTOTYPE_BACKEND", "xdotool")
+        .env("PATH", &path)
+        .arg("--no-keyring")
+        .args(["type", "login"])
+        .write_stdin(format!("{}\n", passphrase))
+        .assert()
+        .success();
+
+    assert_eq!(
+        std::fs::read_to_string(&log).unwrap(),
+        "type --clearmodifiers --file -\nhunter2\n"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn failing_hooks_abort() {
+    let dir = tempdir();
+    let config = dir.path().join("config.toml");
+    let hooks = dir.path().join("hooks");
+    let passphrase = "hooked";
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .arg("init")
+        .write_stdin(format!("{}\n", passphrase))
+        .assert()
+        .success();
+
+    std::fs::create_dir(&hooks).unwrap();
+    write_script(
+        &hooks.join("pre_load"),
+        "#!/bin/sh\necho conflict\nexit 1\n",
+    );
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .arg("list")
+        .assert()
+        .code(8)
+        .stdout("Running pre_load hook\npre_load: conflict\n")
+        .stderr("Error: pre_load hook failed (exit status: 1)\n");
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .args(["config", "set", "hooks.pre_load.on_failure", "warn"])
+        .assert()
+        .success();
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .arg("list")
+        .write_stdin(format!("{}\n", passphrase))
+        .assert()
+        .success()
+        .stdout("Running pre_load hook\npre_load: conflict\nEnter passphrase: ")
+        .stderr("Warning: pre_load hook failed (exit status: 1)\n");
+
+    // a pre_save hook can veto changes
+    std::fs::remove_file(hooks.join("pre_load")).unwrap();
+    write_script(&hooks.join("pre_save"), "#!/bin/sh\nexit 3\n");
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .arg("new")
+        .write_stdin(format!("{}\nvetoed\npassword", passphrase))
+        .assert()
+        .code(8)
+        .stderr("Error: pre_save hook failed (exit status: 3)\n");
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .arg("list")
+        .write_stdin(format!("{}\n", passphrase))
+        .assert()
+        .success()
+        .stdout("Enter passphrase: ");
+}
+
+#[cfg(unix)]
+#[test]
+fn hook_context() {
+    let dir = tempdir();
+    let hooks = dir.path().join("hooks");
+    let log = dir.path().join("log");
+    let passphrase = "context";
+
+    passage()
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .arg("init")
+        .write_stdin(format!("{}\n", passphrase))
+        .assert()
+        .success();
+
+    std::fs::create_dir(&hooks).unwrap();
+    write_script(
+        &hooks.join("post_save"),
+        &format!(
+            "#!/bin/sh\n\
+             echo \"$1|$PASSAGE_HOOK|$PASSAGE_EVENT|$PASSAGE_COMMAND|$PASSAGE_ENTRIES|\
+             $PASSAGE_STORAGE_FILE|$PASSAGE_VERSION\" >> {log}\n\
+             cat >> {log}\n\
+             echo >> {log}\n",
+            log = log.display()
+        ),
+    );
+
+    passage()
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .arg("new")
+        .write_stdin(format!("{}\nwork/aws\nsecret-password", passphrase))
+        .assert()
+        .success();
+
+    let storage_file = dir.path().join("entries.toml.age");
+    let version = env!("CARGO_PKG_VERSION");
+    let log = std::fs::read_to_string(&log).unwrap();
+    assert_eq!(
+        log,
+        format!(
+            "new_entry|post_save|new_entry|new|work/aws|{storage}|{version}\n\
+             {{\"hook\":\"post_save\",\"event\":\"new_entry\",\"command\":\"new\",\
+             \"entries\":[\"work/aws\"],\"storage_file\":\"{storage}\",\"version\":\"{version}\"}}\n",
+            storage = storage_file.display(),
+            version = version
+        )
+    );
+    assert!(!log.contains("secret-password"));
+}
+
+#[cfg(unix)]
+#[test]
+fn hook_timeout_and_output() {
+    let dir = tempdir();
+    let config = dir.path().join("config.toml");
+    let hooks = dir.path().join("hooks");
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .arg("init")
+        .write_stdin("timeout\n")
+        .assert()
+        .success();
+
+    std::fs::create_dir(&hooks).unwrap();
+    // non UTF-8 output must not make passage fail
+    write_script(
+        &hooks.join("pre_load"),
+        "#!/bin/sh\nprintf 'caf\\351\\n'\necho oops >&2\nsleep 30\n",
+    );
+
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .args(["config", "set", "hooks.pre_load.timeout", "1"])
+        .assert()
+        .success();
+
+    let start = std::time::Instant::now();
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .arg("list")
+        .assert()
+        .code(8)
+        .stdout(
+            predicate::str::starts_with("Running pre_load hook\n")
+                .and(predicate::str::contains("pre_load: caf\u{FFFD}\n"))
+                .and(predicate::str::contains("pre_load: oops\n")),
+        )
+        .stderr("Error: pre_load hook timed out after 1 seconds\n");
+    // the `sleep` started by the hook got killed as well
+    assert!(start.elapsed() < std::time::Duration::from_secs(20));
+
+    // a process left running in the background keeps the output of the hook open
+    write_script(
+        &hooks.join("pre_load"),
+        "#!/bin/sh\necho started\nsleep 30 &\n",
+    );
+
+    let start = std::time::Instant::now();
+    passage()
+        .env("PASSAGE_CONFIG", &config)
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .arg("list")
+        .write_stdin("timeout\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("pre_load: started\n"));
+    assert!(start.elapsed() < std::time::Duration::from_secs(20));
+}
+
+#[cfg(unix)]
+#[test]
+fn hook_event_matrix() {
+    const LOAD: &[&str] = &["pre_load", "post_load"];
+    const SAVE: &[&str] = &["pre_save", "post_save"];
+    const ALL: &[&str] = &["pre_load", "post_load", "pre_save", "post_save"];
+
+    let dir = tempdir();
+    let hooks = dir.path().join("hooks");
+    let log = dir.path().join("hooks.log");
+    let import = dir.path().join("import.toml");
+
+    std::fs::create_dir(&hooks).unwrap();
+    for hook in ALL {
+        write_script(
+            &hooks.join(hook),
+            &format!(
+                "#!/bin/sh\necho \"$PASSAGE_HOOK $1\" >> {}\n",
+                log.display()
+            ),
+        );
+    }
+    std::fs::write(&import, "[imported]\npassword = 'pw'\n").unwrap();
+    let import = import.to_str().unwrap();
+    let storage = dir.path().join("entries.toml.age");
+    let storage = storage.to_str().unwrap();
+    let merged = dir.path().join("merged.age");
+
+    let matrix: &[(&[&str], &str, &[&str], &str)] = &[
+        (&["init"], "p\n", SAVE, "init_storage"),
+        (&["new"], "p\nentry\npw", ALL, "new_entry"),
+        (&["list"], "p\n", LOAD, "list_entries"),
+        (&["show", "--on-screen", "entry"], "p\n", LOAD, "show_entry"),
+        (&["find", "ent"], "p\n", LOAD, "find_entries"),
+        (&["edit", "entry"], "p\npw2\n", ALL, "edit_entry"),
+        (&["mv", "entry", "moved"], "p\n", ALL, "move_entry"),
+        (&["import", import], "p\n", ALL, "import_entries"),
+        (&["rekey"], "p\nq\nq\n", ALL, "rekey_storage"),
+        (&["remove", "moved"], "q\n", ALL, "remove_entry"),
+        (&["info"], "", &[], ""),
+        (&["git", "init"], "", &[], ""),
+        (&["git", "sync"], "q\n", LOAD, "sync_storage"),
+        (
+            &[
+                "merge",
+                storage,
+                storage,
+                storage,
+                "--out",
+                merged.to_str().unwrap(),
+            ],
+            "q\n",
+            LOAD,
+            "merge_storage",
+        ),
+    ];
+
+    for (args, stdin, hooks, event) in matrix {
+        passage_git(dir.path())
+            .args(*args)
+            .write_stdin(*stdin)
+            .assert()
+            .success();
+
+        let expected: String = hooks
+            .iter()
+            .map(|hook| format!("{} {}\n", hook, event))
+            .collect();
+        assert_eq!(
+            std::fs::read_to_string(&log).unwrap_or_default(),
+            expected,
+            "hooks run by `passage {}`",
+            args.join(" ")
+        );
+        let _ = std::fs::remove_file(&log);
+    }
+}
+
+#[test]
+fn mv_rekey_import() {
+    let dir = tempdir();
+    let import = dir.path().join("import.toml");
+
+    passage()
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .arg("init")
+        .write_stdin("old\n")
+        .assert()
+        .success();
+
+    std::fs::write(
+        &import,
+        "[a]\npassword = 'pw-a'\n[b]\npassword = 'pw-b'\nusername = 'bob'\n",
+    )
+    .unwrap();
+    passage()
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .arg("import")
+        .arg(&import)
+        .write_stdin("old\n")
+        .assert()
+        .success()
+        .stdout("Enter passphrase: Imported 2 entries\n");
+
+    std::fs::write(&import, "[a]\npassword = 'changed'\n").unwrap();
+    let storage = std::fs::read(dir.path().join("entries.toml.age")).unwrap();
+    passage()
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .arg("import")
+        .arg(&import)
+        .write_stdin("old\n")
+        .assert()
+        .success()
+        .stdout("Enter passphrase: Imported 0 entries\n")
+        .stderr("Skipping a, it already exists\n");
+    // nothing got imported, so the storage wasn't written again
+    assert_eq!(
+        std::fs::read(dir.path().join("entries.toml.age")).unwrap(),
+        storage
+    );
+
+    passage()
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .args(["mv", "a", "b"])
+        .write_stdin("old\n")
+        .assert()
+        .code(7)
+        .stderr("Error: entry already exists: b\n");
+
+    passage()
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .args(["mv", "a", "c"])
+        .write_stdin("old\n")
+        .assert()
+        .success();
+
+    passage()
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .arg("rekey")
+        .write_stdin("old\nnew\nnot new\n")
+        .assert()
+        .code(1)
+        .stderr("Error: passphrases don't match\n");
+
+    passage()
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .arg("rekey")
+        .write_stdin("old\nnew\nnew\n")
+        .assert()
+        .success()
+        .stdout("Enter passphrase: New passphrase: Repeat new passphrase: ");
+
+    passage()
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .args(["show", "--on-screen", "c"])
+        .write_stdin("new\n")
+        .assert()
+        .success()
+        .stdout("Enter passphrase: pw-a\n");
+
+    passage()
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .arg("list")
+        .write_stdin("old\n")
+        .assert()
+        .code(5);
+}
+
+#[cfg(unix)]
+#[test]
+fn hook_directories() {
+    let dir = tempdir();
+    let hooks = dir.path().join("hooks");
+    let post_load = hooks.join("post_load.d");
+
+    passage()
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .arg("init")
+        .write_stdin("p\n")
+        .assert()
+        .success();
+
+    std::fs::create_dir_all(&post_load).unwrap();
+    write_script(&hooks.join("post_load"), "#!/bin/sh\necho single\n");
+    write_script(&post_load.join("20-audit"), "#!/bin/sh\necho audit\n");
+    write_script(&post_load.join("10-git"), "#!/bin/sh\necho git\n");
+    write_script(&post_load.join(".hidden"), "#!/bin/sh\necho hidden\n");
+    std::fs::write(post_load.join("15-notes"), "not a script").unwrap();
+
+    passage()
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("info")
+        .assert()
+        .success()
+        .stdout(predicate::str::ends_with(
+            "  post_load: post_load, post_load.d/10-git, \
+             post_load.d/15-notes (not executable, skipped), post_load.d/20-audit\n",
+        ));
+
+    passage()
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .arg("list")
+        .write_stdin("p\n")
+        .assert()
+        .success()
+        .stdout(
+            "Enter passphrase: Running post_load hook\npost_load: single\n\
+             Running post_load.d/10-git hook\npost_load.d/10-git: git\n\
+             Running post_load.d/20-audit hook\npost_load.d/20-audit: audit\n",
+        )
+        .stderr("Warning: skipping post_load.d/15-notes hook, it isn't executable\n");
+
+    // a failing script skips the ones after it
+    write_script(&post_load.join("10-git"), "#!/bin/sh\nexit 1\n");
+
+    passage()
+        .env("PASSAGE_STORAGE_FOLDER", dir.path())
+        .arg("--no-keyring")
+        .arg("list")
+        .write_stdin("p\n")
+        .assert()
+        .code(8)
+        .stdout(
+            "Enter passphrase: Running post_load hook\npost_load: single\n\
+             Running post_load.d/10-git hook\n",
+        )
+        .stderr("Error: post_load.d/10-git hook failed (exit status: 1)\n");
+}
+
+/// Runs git with a fixed identity, so commits work without any global git config
+fn git(dir: &Path, args: &[&str]) {
+    let status = std::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .envs(GIT_IDENTITY.iter().copied())
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success(), "git {} failed", args.join(" "));
+}
+
+const GIT_IDENTITY: &[(&str, &str)] = &[
+    ("GIT_AUTHOR_NAME", "passage"),
+    ("GIT_AUTHOR_EMAIL", "passage@example.com"),
+    ("GIT_COMMITTER_NAME", "passage"),
+    ("GIT_COMMITTER_EMAIL", "passage@example.com"),
+];
+
+/// Runs passage on the storage folder `dir` with the identity git commits with
+fn passage_git(dir: &Path) -> Command {
+    let mut cmd = passage_in(dir);
+    cmd.envs(GIT_IDENTITY.iter().copied());
+    cmd
+}
+
+#[test]
+fn git_sync() {
+    let dir = tempdir();
+    let remote = dir.path().join("remote.git");
+    let laptop = dir.path().join("laptop");
+    let desktop = dir.path().join("desktop");
+    git(dir.path(), &["init", "--quiet", "--bare", "remote.git"]);
+    passage_git(&laptop)
+        .arg("init")
+        .write_stdin("p\n")
+        .assert()
+        .success();
+    passage_git(&laptop)
+        .args(["git", "init", remote.to_str().unwrap()])
+        .assert()
+        .success();
+    passage_git(&laptop)
+        .args(["new", "--username", "bob"])
+        .write_stdin("p\nmail\nsecret")
+        .assert()
+        .success();
+    passage_git(&laptop)
+        .args(["new"])
+        .write_stdin("p\nbank\n1234")
+        .assert()
+        .success();
+    passage_git(&laptop)
+        .args(["git", "sync"])
+        .write_stdin("p\n")
+        .assert()
+        .success()
+        .stdout("Enter passphrase: Committed: Add bank, mail\nPushed to origin\n");
+
+    git(
+        dir.path(),
+        &["clone", "--quiet", remote.to_str().unwrap(), "desktop"],
+    );
+
+    // both sides change different entries
+    passage_git(&laptop)
+        .args(["edit", "bank"])
+        .write_stdin("p\n5678\n")
+        .assert()
+        .success();
+    passage_git(&laptop)
+        .args(["git", "sync"])
+        .write_stdin("p\n")
+        .assert()
+        .success()
+        .stdout("Enter passphrase: Committed: Update bank\nPushed to origin\n");
+    passage_git(&desktop)
+        .args(["new"])
+        .write_stdin("p\nforum\nhunter2")
+        .assert()
+        .success();
+    passage_git(&desktop)
+        .args(["remove", "mail"])
+        .write_stdin("p\n")
+        .assert()
+        .success();
+    passage_git(&desktop)
+        .args(["git", "sync"])
+        .write_stdin("p\n")
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::starts_with(
+                "Enter passphrase: Committed: Add forum; remove mail\n\
+                 Merged changes from origin/",
+            )
+            .and(predicate::str::ends_with("\nPushed to origin\n")),
+        );
+    passage_git(&laptop)
+        .args(["git", "sync"])
+        .write_stdin("p\n")
+        .assert()
+        .success()
+        .stdout("Enter passphrase: Pulled changes from origin\n");
+
+    for storage in &[&laptop, &desktop] {
+        passage_git(storage)
+            .args(["show", "--on-screen", "bank"])
+            .write_stdin("p\n")
+            .assert()
+            .success()
+            .stdout("Enter passphrase: 5678\n");
+        passage_git(storage)
+            .args(["show", "--on-screen", "forum"])
+            .write_stdin("p\n")
+            .assert()
+            .success()
+            .stdout("Enter passphrase: hunter2\n");
+        passage_git(storage)
+            .args(["show", "--on-screen", "mail"])
+            .write_stdin("p\n")
+            .assert()
+            .code(6);
+    }
+
+    // both sides change the same entry
+    passage_git(&laptop)
+        .args(["edit", "bank"])
+        .write_stdin("p\nlaptop\n")
+        .assert()
+        .success();
+    passage_git(&laptop)
+        .args(["git", "sync"])
+        .write_stdin("p\n")
+        .assert()
+        .success();
+    passage_git(&desktop)
+        .args(["edit", "bank"])
+        .write_stdin("p\ndesktop\n")
+        .assert()
+        .success();
+    passage_git(&desktop)
+        .args(["git", "sync"])
+        .write_stdin("p\n")
+        .assert()
+        .code(1)
+        .stdout(predicate::str::ends_with(
+            "Conflict in bank:\n  ours: changed password\n  theirs: changed password\n\
+             Keep (o)urs or (t)heirs? ",
+        ))
+        .stderr("Error: unresolved conflict in bank\n");
+    passage_git(&desktop)
+        .args(["git", "sync", "--prefer", "theirs"])
+        .write_stdin("p\n")
+        .assert()
+        .success();
+    passage_git(&desktop)
+        .args(["show", "--on-screen", "bank"])
+        .write_stdin("p\n")
+        .assert()
+        .success()
+        .stdout("Enter passphrase: laptop\n");
+
+    passage_git(&laptop)
+        .args(["git", "log"])
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains("Update bank\n")
+                .and(predicate::str::contains("Merge entries from origin/"))
+                .and(predicate::str::contains("Add bank, mail\n"))
+                .and(predicate::str::ends_with("Initialize password store\n")),
+        );
+    let log = json_stdout(
+        passage_git(&laptop)
+            .args(["--output", "json", "git", "log"])
+            .assert()
+            .success(),
+    );
+    let commits = log["commits"].as_array().unwrap();
+    assert_eq!(commits[0]["message"], "Update bank");
+    assert_eq!(
+        commits.last().unwrap()["message"],
+        "Initialize password store"
+    );
+    for commit in commits {
+        let mut keys: Vec<&String> = commit.as_object().unwrap().keys().collect();
+        keys.sort();
+        assert_eq!(keys, ["date", "id", "message"]);
+    }
+}
+
+#[test]
+fn merge_storage_files() {
+    let dir = tempdir();
+    let storage = dir.path().join("entries.toml.age");
+    let version = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+    passage_in(dir.path())
+        .arg("init")
+        .write_stdin("p\n")
+        .assert()
+        .success();
+    passage_in(dir.path())
+        .arg("new")
+        .write_stdin("p\nshared\nbase")
+        .assert()
+        .success();
+    passage_in(dir.path())
+        .arg("new")
+        .write_stdin("p\nold\nbase")
+        .assert()
+        .success();
+    std::fs::copy(&storage, version("base")).unwrap();
+
+    passage_in(dir.path())
+        .args(["edit", "shared"])
+        .write_stdin("p\nours\n")
+        .assert()
+        .success();
+    passage_in(dir.path())
+        .arg("new")
+        .write_stdin("p\nmine\npw")
+        .assert()
+        .success();
+    std::fs::copy(&storage, version("ours")).unwrap();
+
+    std::fs::copy(version("base"), &storage).unwrap();
+    passage_in(dir.path())
+        .args(["edit", "shared", "--username", "bob"])
+        .write_stdin("p\n")
+        .assert()
+        .success();
+    passage_in(dir.path())
+        .args(["remove", "old"])
+        .write_stdin("p\n")
+        .assert()
+        .success();
+    std::fs::copy(&storage, version("theirs")).unwrap();
+
+    // without an answer the conflict stays unresolved
+    passage_in(dir.path())
+        .args([
+            "merge",
+            &version("base"),
+            &version("ours"),
+            &version("theirs"),
+        ])
+        .args(["--out", &version("merged")])
+        .write_stdin("p\n")
+        .assert()
+        .code(1)
+        .stdout(
+            "Enter passphrase: Conflict in shared:\n  ours: changed password\n  \
+             theirs: changed username\nKeep (o)urs or (t)heirs? ",
+        )
+        .stderr("Error: unresolved conflict in shared\n");
+    assert!(!dir.path().join("merged").exists());
+
+    passage_in(dir.path())
+        .args([
+            "merge",
+            &version("base"),
+            &version("ours"),
+            &version("theirs"),
+        ])
+        .write_stdin("p\nmaybe\nt\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::ends_with(
+            "Keep (o)urs or (t)heirs? Keep (o)urs or (t)heirs? Merged 2 entries, 1 conflicts\n",
+        ));
+
+    std::fs::copy(version("ours"), &storage).unwrap();
+    passage_in(dir.path())
+        .arg("list")
+        .write_stdin("p\n")
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains("mine\n")
+                .and(predicate::str::contains("shared\n"))
+                .and(predicate::str::contains("old").not()),
+        );
+    passage_in(dir.path())
+        .args(["show", "--on-screen", "shared"])
+        .write_stdin("p\n")
+        .assert()
+        .success()
+        .stdout("Enter passphrase: base\n");
+}
+
+#[test]
+fn git_sync_after_rekey() {
+    let dir = tempdir();
+    let remote = dir.path().join("remote.git");
+    let laptop = dir.path().join("laptop");
+    let desktop = dir.path().join("desktop");
+    git(dir.path(), &["init", "--quiet", "--bare", "remote.git"]);
+    passage_git(&laptop)
+        .arg("init")
+        .write_stdin("p\n")
+        .assert()
+        .success();
+    passage_git(&laptop)
+        .args(["git", "init", remote.to_str().unwrap()])
+        .assert()
+        .success();
+    passage_git(&laptop)
+        .arg("new")
+        .write_stdin("p\nmail\nsecret")
+        .assert()
+        .success();
+    passage_git(&laptop)
+        .args(["git", "sync"])
+        .write_stdin("p\n")
+        .assert()
+        .success();
+    git(
+        dir.path(),
+        &["clone", "--quiet", remote.to_str().unwrap(), "desktop"],
+    );
+
+    // the last commit can't be decrypted with the new passphrase anymore
+    passage_git(&laptop)
+        .arg("rekey")
+        .write_stdin("p\nq\nq\n")
+        .assert()
+        .success();
+    passage_git(&laptop)
+        .args(["git", "sync"])
+        .write_stdin("q\n")
+        .assert()
+        .success()
+        .stdout("Enter passphrase: Committed: Re-encrypt storage\nPushed to origin\n");
+
+    // merging with the rekeyed side asks for its passphrase
+    passage_git(&desktop)
+        .arg("new")
+        .write_stdin("p\nforum\nhunter2")
+        .assert()
+        .success();
+    passage_git(&desktop)
+        .args(["git", "sync"])
+        .write_stdin("p\nq\n")
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::starts_with(
+                "Enter passphrase: Committed: Add forum\n\
+                 Enter the other passphrase: Merged changes from origin/",
+            )
+            .and(predicate::str::ends_with("\nPushed to origin\n")),
+        )
+        .stderr(
+            "The storage was rekeyed on one side only, @{upstream} uses a different \
+             passphrase\n",
+        );
+    passage_git(&desktop)
+        .args(["show", "--on-screen", "mail"])
+        .write_stdin("p\n")
+        .assert()
+        .success()
+        .stdout("Enter passphrase: secret\n");
+}
+
+#[test]
+fn git_merge_driver() {
+    let dir = tempdir();
+    let remote = dir.path().join("remote.git");
+    let laptop = dir.path().join("laptop");
+    let desktop = dir.path().join("desktop");
+    git(dir.path(), &["init", "--quiet", "--bare", "remote.git"]);
+    passage_git(&laptop)
+        .arg("init")
+        .write_stdin("p\n")
+        .assert()
+        .success();
+    passage_git(&laptop)
+        .args(["git", "init", remote.to_str().unwrap()])
+        .assert()
+        .success();
+    passage_git(&laptop)
+        .args(["git", "sync"])
+        .write_stdin("p\n")
+        .assert()
+        .success();
+    git(
+        dir.path(),
+        &["clone", "--quiet", remote.to_str().unwrap(), "desktop"],
+    );
+    // the driver knows which storage it merges
+    Command::new("git")
+        .arg("-C")
+        .arg(&laptop)
+        .args(["config", "merge.passage.driver"])
+        .assert()
+        .success()
+        .stdout(format!(
+            "PASSAGE_STORAGE_FOLDER='{}' passage merge %O %A %B\n",
+            laptop.display()
+        ));
+
+    passage_git(&laptop)
+        .arg("new")
+        .write_stdin("p\nlaptop\npw")
+        .assert()
+        .success();
+    passage_git(&laptop)
+        .args(["git", "sync"])
+        .write_stdin("p\n")
+        .assert()
+        .success();
+    passage_git(&desktop)
+        .arg("new")
+        .write_stdin("p\ndesktop\npw")
+        .assert()
+        .success();
+    git(&desktop, &["commit", "--quiet", "-am", "Add desktop"]);
+
+    // a plain `git pull` merges through `passage merge`
+    let driver = format!(
+        "{} --no-keyring merge %O %A %B",
+        assert_cmd::cargo::cargo_bin("passage").display()
+    );
+    Command::new("git")
+        .arg("-C")
+        .arg(&desktop)
+        .args(["-c", &format!("merge.passage.driver={}", driver)])
+        .args(["pull", "--quiet", "--no-rebase", "--no-edit"])
+        .envs(GIT_IDENTITY.iter().copied())
+        .write_stdin("p\n")
+        .assert()
+        .success();
+
+    passage_git(&desktop)
+        .arg("list")
+        .write_stdin("p\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("laptop\n").and(predicate::str::contains("desktop\n")));
+}
+
+#[test]
+fn vaults() {
+    let dir = tempdir();
+    let config = dir.path().join("config.toml");
+    let personal = dir.path().join("personal");
+    let team = dir.path().join("team");
+    passage_with_config(&config)
+        .args(["vault", "add", "personal", personal.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(format!("Added vault personal ({})\n", personal.display()));
+    passage_with_config(&config)
+        .args(["vault", "add", "team", team.to_str().unwrap()])
+        .assert()
+        .success();
+    passage_with_config(&config)
+        .args(["vault", "add", "team", "elsewhere"])
+        .assert()
+        .code(1)
+        .stderr("Error: vault already exists: team\n");
+    passage_with_config(&config)
+        .args(["vault", "add", "my.vault"])
+        .assert()
+        .code(1);
+
+    for (vault, passphrase, entry) in &[("personal", "p1", "mail"), ("team", "p2", "ci")] {
+        passage_with_config(&config)
+            .args(["--vault", vault, "init"])
+            .write_stdin(format!("{}\n", passphrase))
+            .assert()
+            .success();
+        passage_with_config(&config)
+            .args(["--vault", vault, "new"])
+            .write_stdin(format!("{}\n{}\nsecret", passphrase, entry))
+            .assert()
+            .success();
+    }
+    assert!(personal.join("entries.toml.age").exists());
+    assert!(team.join("entries.toml.age").exists());
+
+    passage_with_config(&config)
+        .args(["vault", "use", "team"])
+        .assert()
+        .success()
+        .stdout("Using vault team\n");
+    passage_with_config(&config)
+        .args(["vault", "list"])
+        .assert()
+        .success()
+        .stdout(format!(
+            "  personal ({})\n* team ({})\n",
+            personal.display(),
+            team.display()
+        ));
+    passage_with_config(&config)
+        .arg("list")
+        .write_stdin("p2\n")
+        .assert()
+        .success()
+        .stdout("Enter passphrase: ci\n");
+    passage_with_config(&config)
+        .arg("info")
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(format!(
+            "Vault: team\nStorage file: {}\n",
+            team.join("entries.toml.age").display()
+        )));
+
+    // --vault and PASSAGE_VAULT take precedence over the config file
+    passage_with_config(&config)
+        .args(["list", "--vault", "personal"])
+        .write_stdin("p1\n")
+        .assert()
+        .success()
+        .stdout("Enter passphrase: mail\n");
+    passage_with_config(&config)
+        .env("PASSAGE_VAULT", "personal")
+        .arg("list")
+        .write_stdin("p1\n")
+        .assert()
+        .success()
+        .stdout("Enter passphrase: mail\n");
+    passage_with_config(&config)
+        .args(["--vault", "nope", "list"])
+        .assert()
+        .code(13)
+        .stderr("Error: unknown vault: nope\n");
+
+    passage_with_config(&config)
+        .args(["vault", "remove", "team"])
+        .assert()
+        .success();
+    assert!(team.join("entries.toml.age").exists());
+    passage_with_config(&config)
+        .args(["vault", "list"])
+        .assert()
+        .success()
+        .stdout(format!("  personal ({})\n", personal.display()));
+    passage_with_config(&config)
+        .args(["vault", "use"])
+        .assert()
+        .success()
+        .stdout("Using the default storage folder\n");
+}
+
+#[test]
+fn find_and_copy_between_vaults() {
+    let dir = tempdir();
+    let config = dir.path().join("config.toml");
+    let personal = dir.path().join("personal");
+    let team = dir.path().join("team");
+    for (vault, folder, passphrase) in &[("personal", &personal, "p1"), ("team", &team, "p2")] {
+        passage_with_config(&config)
+            .args(["vault", "add", vault, folder.to_str().unwrap()])
+            .assert()
+            .success();
+        passage_with_config(&config)
+            .args(["--vault", vault, "init"])
+            .write_stdin(format!("{}\n", passphrase))
+            .assert()
+            .success();
+    }
+    passage_with_config(&config)
+        .args(["--vault", "personal", "new", "--username", "deploy-bot"])
+        .write_stdin("p1\naws\nsecret")
+        .assert()
+        .success();
+    passage_with_config(&config)
+        .args(["--vault", "team", "new"])
+        .write_stdin("p2\naws-staging\nstaging")
+        .assert()
+        .success();
+    passage_with_config(&config)
+        .args(["--vault", "team", "new"])
+        .write_stdin("p2\ngithub\ngh")
+        .assert()
+        .success();
+
+    passage_with_config(&config)
+        .args(["--vault", "team", "find", "AWS"])
+        .write_stdin("p2\n")
+        .assert()
+        .success()
+        .stdout("Enter passphrase: aws-staging\n");
+    passage_with_config(&config)
+        .args(["find", "--all-vaults", "aws"])
+        .write_stdin("p1\np2\n")
+        .assert()
+        .success()
+        .stdout(
+            "Enter passphrase for personal: personal: aws\n\
+             Enter passphrase for team: team: aws-staging\n",
+        );
+    // usernames are searched too
+    passage_with_config(&config)
+        .args(["find", "--all-vaults", "bot"])
+        .write_stdin("p1\np2\n")
+        .assert()
+        .success()
+        .stdout("Enter passphrase for personal: personal: aws\nEnter passphrase for team: ");
+
+    passage_with_config(&config)
+        .args(["cp", "aws", "--from", "personal", "--to", "team"])
+        .write_stdin("p1\np2\n")
+        .assert()
+        .success()
+        .stdout(
+            "Enter passphrase for personal: Enter passphrase for team: \
+             Copied aws from personal to team\n",
+        );
+    passage_with_config(&config)
+        .args(["--vault", "team", "show", "--on-screen", "aws"])
+        .write_stdin("p2\n")
+        .assert()
+        .success()
+        .stdout("Enter passphrase: secret\n");
+    passage_with_config(&config)
+        .args(["cp", "aws", "--from", "personal", "--to", "team"])
+        .write_stdin("p1\np2\n")
+        .assert()
+        .code(7)
+        .stderr("Error: entry already exists: aws (in team)\n");
+    passage_with_config(&config)
+        .args(["cp", "github", "--from", "personal", "--to", "team"])
+        .write_stdin("p1\n")
+        .assert()
+        .code(6)
+        .stderr("Error: entry not found: github (in personal)\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn copy_runs_hooks_of_both_vaults() {
+    let dir = tempdir();
+    let config = dir.path().join("config.toml");
+    let log = dir.path().join("hooks.log");
+    for vault in &["a", "b"] {
+        let folder = dir.path().join(vault);
+        passage_with_config(&config)
+            .args(["vault", "add", vault, folder.to_str().unwrap()])
+            .assert()
+            .success();
+        passage_with_config(&config)
+            .args(["--vault", vault, "init"])
+            .write_stdin("p\n")
+            .assert()
+            .success();
+        std::fs::create_dir(folder.join("hooks")).unwrap();
+        for hook in &["pre_load", "post_load", "pre_save", "post_save"] {
+            write_script(
+                &folder.join("hooks").join(hook),
+                &format!(
+                    "#!/bin/sh\necho \"{} $PASSAGE_HOOK $1\" >> {}\n",
+                    vault,
+                    log.display()
+                ),
+            );
+        }
+    }
+    passage_with_config(&config)
+        .args(["--vault", "a", "new"])
+        .write_stdin("p\nentry\npw")
+        .assert()
+        .success();
+    std::fs::remove_file(&log).unwrap();
+
+    passage_with_config(&config)
+        .args(["cp", "entry", "--from", "a", "--to", "b"])
+        .write_stdin("p\np\n")
+        .assert()
+        .success();
+    assert_eq!(
+        std::fs::read_to_string(&log).unwrap(),
+        "a pre_load copy_entry\na post_load copy_entry\n\
+         b pre_load copy_entry\nb post_load copy_entry\n\
+         b pre_save copy_entry\nb post_save copy_entry\n"
+    );
+}
+
+/// A stand-in for an object storage like MinIO or a WebDAV server like Nextcloud, keeping
+/// objects in memory and supporting conditional writes. It only takes requests with an
+/// `Authorization` header starting with `authorization` and logs them, with `race` set
+/// every object gets changed by "someone else" right after it was read.
+#[derive(Default)]
+struct ObjectStore {
+    objects: std::collections::HashMap<String, (Vec<u8>, u32)>,
+    requests: Vec<String>,
+    race: bool,
+    authorization: String,
+    /// Statuses of successful writes creating and replacing an object
+    put_statuses: (u16, u16),
+}
+
+fn object_store(
+    authorization: &str,
+    put_statuses: (u16, u16),
+) -> (String, std::sync::Arc<std::sync::Mutex<ObjectStore>>) {
+    let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+    let stub = std::sync::Arc::new(std::sync::Mutex::new(ObjectStore {
+        authorization: authorization.to_string(),
+        put_statuses,
+        ..ObjectStore::default()
+    }));
+    let shared = std::sync::Arc::clone(&stub);
+    std::thread::spawn(move || {
+        for mut request in server.incoming_requests() {
+            let header = |name: &'static str| {
+                request
+                    .headers()
+                    .iter()
+                    .find(|h| h.field.equiv(name))
+                    .map(|h| h.value.to_string())
+            };
+            let authorization = header("authorization");
+            let if_match = header("if-match");
+            let if_none_match = header("if-none-match");
+            let mut body = vec![];
+            std::io::Read::read_to_end(request.as_reader(), &mut body).unwrap();
+
+            let mut stub = shared.lock().unwrap();
+            let authorized = authorization.is_some_and(|a| a.starts_with(&stub.authorization));
+            let method = request.method().to_string();
+            let log = match (&if_match, &if_none_match) {
+                (Some(etag), _) => format!("{} If-Match: {}", method, etag),
+                (_, Some(etag)) => format!("{} If-None-Match: {}", method, etag),
+                _ => method.clone(),
+            };
+            stub.requests.push(log);
+            let path = request.url().to_string();
+            let current = stub
+                .objects
+                .get(&path)
+                .map(|(data, version)| (data.clone(), format!("\"{}\"", version)));
+
+            let (status, data, etag) = match (authorized, method.as_str(), current) {
+                (false, _, _) => (401, vec![], None),
+                (_, "GET", Some((data, etag))) => {
+                    if stub.race {
+                        stub.objects.get_mut(&path).unwrap().1 += 1;
+                    }
+                    (200, data, Some(etag))
+                }
+                (_, "HEAD", Some((_, etag))) => (200, vec![], Some(etag)),
+                (_, "GET", None) | (_, "HEAD", None) => (404, vec![], None),
+                (_, "PUT", current) => {
+                    let etag = current.map(|(_, etag)| etag);
+                    let expected = if_match.is_some() && if_match == etag
+                        || if_none_match.as_deref() == Some("*") && etag.is_none();
+                    if expected {
+                        let (created, replaced) = stub.put_statuses;
+                        let status = if etag.is_none() { created } else { replaced };
+                        let version = stub.objects.get(&path).map_or(1, |(_, v)| v + 1);
+                        stub.objects.insert(path, (body, version));
+                        (status, vec![], Some(format!("\"{}\"", version)))
+                    } else {
+                        (412, vec![], None)
+                    }
+                }
+                _ => (405, vec![], None),
+            };
+            let mut response = tiny_http::Response::from_data(data).with_status_code(status);
+            if let Some(etag) = etag {
+                response.add_header(tiny_http::Header::from_bytes("ETag", etag).unwrap());
+            }
+            request.respond(response).unwrap();
+        }
+    });
+    (url, stub)
+}
+
+#[test]
+fn s3_backend() {
+    let dir = tempdir();
+    let (url, stub) = object_store("AWS4-HMAC-SHA256 Credential=test-key/", (200, 200));
+    let passage_s3 = || {
+        let mut cmd = passage();
+        cmd.env("PASSAGE_STORAGE_FOLDER", dir.path())
+            .env("PASSAGE_BACKEND", "s3")
+            .env("PASSAGE_S3_ENDPOINT", &url)
+            .env("PASSAGE_S3_BUCKET", "team")
+            .env("AWS_ACCESS_KEY_ID", "test-key")
+            .env("AWS_SECRET_ACCESS_KEY", "test-secret")
+            .env_remove("AWS_SESSION_TOKEN")
+            .arg("--no-keyring");
+        cmd
+    };
+
+    passage_s3()
+        .arg("init")
+        .write_stdin("p\n")
+        .assert()
+        .success();
+    passage_s3()
+        .arg("new")
+        .write_stdin("p\ngithub\nhunter2\n")
+        .assert()
+        .success();
+    passage_s3()
+        .args(["show", "--on-screen", "github"])
+        .write_stdin("p\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::ends_with("hunter2\n"));
+    passage_s3()
+        .arg("info")
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(format!(
+            "Storage object: s3://team/entries.toml.age ({})\n",
+            url
+        )));
+    assert!(!dir.path().join("entries.toml.age").exists());
+    {
+        let stub = stub.lock().unwrap();
+        assert!(stub.objects.contains_key("/team/entries.toml.age"));
+        assert!(stub.requests.contains(&"PUT If-None-Match: *".to_string()));
+        assert!(stub.requests.contains(&"PUT If-Match: \"1\"".to_string()));
+    }
+
+    stub.lock().unwrap().race = true;
+    passage_s3()
+        .args(["remove", "github"])
+        .write_stdin("p\n")
+        .assert()
+        .code(9)
+        .stderr("Error: the storage was changed by someone else since it was read, try again\n");
+
+    passage_s3()
+        .env_remove("AWS_ACCESS_KEY_ID")
+        .arg("list")
+        .assert()
+        .code(1)
+        .stderr("Error: S3 credentials missing, set AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn webdav_backend() {
+    let dir = tempdir();
+    // "chris:app-password"
+    let (server, stub) = object_store("Basic Y2hyaXM6YXBwLXBhc3N3b3Jk", (201, 204));
+    let url = format!("{}/remote.php/dav/files/chris/entries.toml.age", server);
+    let passage_webdav = || {
+        let mut cmd = passage();
+        cmd.env("PASSAGE_STORAGE_FOLDER", dir.path())
+            .env("PASSAGE_BACKEND", "webdav")
+            .env("PASSAGE_WEBDAV_URL", &url)
+            .env("PASSAGE_WEBDAV_USERNAME", "chris")
+            .env("PASSAGE_WEBDAV_PASSWORD_COMMAND", "echo app-password")
+            .arg("--no-keyring");
+        cmd
+    };
+
+    passage_webdav()
+        .arg("init")
+        .write_stdin("p\n")
+        .assert()
+        .success();
+    passage_webdav()
+        .arg("new")
+        .write_stdin("p\ngithub\nhunter2\n")
+        .assert()
+        .success();
+    passage_webdav()
+        .args(["show", "--on-screen", "github"])
+        .write_stdin("p\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::ends_with("hunter2\n"));
+    passage_webdav()
+        .arg("info")
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(format!(
+            "Storage URL: {}\n",
+            url
+        )));
+    assert!(!dir.path().join("entries.toml.age").exists());
+    {
+        let stub = stub.lock().unwrap();
+        assert!(stub
+            .objects
+            .contains_key("/remote.php/dav/files/chris/entries.toml.age"));
+        assert!(stub.requests.contains(&"PUT If-None-Match: *".to_string()));
+        assert!(stub.requests.contains(&"PUT If-Match: \"1\"".to_string()));
+    }
+
+    stub.lock().unwrap().race = true;
+    passage_webdav()
+        .args(["remove", "github"])
+        .write_stdin("p\n")
+        .assert()
+        .code(9)
+        .stderr("Error: the storage was changed by someone else since it was read, try again\n");
+
+    passage_webdav()
+        .env("PASSAGE_WEBDAV_PASSWORD_COMMAND", "echo wrong")
+        .arg("list")
+        .assert()
+        .code(1)
+        .stderr(format!(
+            "Error: the WebDAV server rejected the credentials for {}, check webdav.username \
+             and webdav.password_command\n",
+            url
+        ));
+}
+
+#[test]
+fn completions() {
+    for (shell, function) in &[
+        ("bash", "_passage_names"),
+        ("zsh", "_passage_names"),
+        ("fish", "__passage_complete"),
+    ] {
+        passage()
+            .args(["completions", shell])
+            .assert()
+            .success()
+            .stdout(
+                predicate::str::contains(*function)
+                    .and(predicate::str::contains("passage __complete")),
+            );
+    }
+    passage()
+        .args(["completions", "powershell"])
+        .assert()
+        .code(2);
+
+    let dir = tempdir();
+    let config = dir.path().join("config.toml");
+    // `__complete` has to come first, so these don't use passage_with_config
+    let complete = || {
+        let mut cmd = passage();
+        cmd.env("PASSAGE_CONFIG", &config)
+            .env("PASSAGE_STORAGE_FOLDER", dir.path())
+            .arg("__complete");
+        cmd
+    };
+    for vault in &["team", "personal"] {
+        passage_with_config(&config)
+            .args([
+                "vault",
+                "add",
+                vault,
+                dir.path().join(vault).to_str().unwrap(),
+            ])
+            .assert()
+            .success();
+    }
+    complete()
+        .arg("vaults")
+        .assert()
+        .success()
+        .stdout("personal\nteam\n");
+
+    // without a passphrase in the keyring there is nothing to complete, and no prompt
+    passage_in(dir.path())
+        .env("PASSAGE_CONFIG", &config)
+        .arg("init")
+        .write_stdin("p\n")
+        .assert()
+        .success();
+    complete()
+        .env("PASSAGE_KEYRING", "false")
+        .arg("entries")
+        .assert()
+        .success()
+        .stdout("");
+
+    // a passphrase from passphrase_command is never prompted for either
+    passage_with_config(&config)
+        .args(["config", "set", "passphrase_command", "echo p"])
+        .assert()
+        .success();
+    passage_in(dir.path())
+        .env("PASSAGE_CONFIG", &config)
+        .arg("new")
+        .write_stdin(
+            "github
+secret
+",
+        )
+        .assert()
+        .success()
+        .stdout("New entry: Password for github: ");
+    complete()
+        .env("PASSAGE_KEYRING", "false")
+        .arg("entries")
+        .assert()
+        .success()
+        .stdout("github\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn completions_bash_arguments() {
+    let dir = tempdir();
+    let script = dir.path().join("passage.bash");
+    let output = passage().args(["completions", "bash"]).output().unwrap();
+    std::fs::write(&script, output.stdout).unwrap();
+
+    // `passage` is stubbed out to see what the script asks for and what it does with
+    // the answer
+    Command::new("bash")
+        .current_dir(dir.path())
+        .arg("-c")
+        .arg(
+            "source passage.bash
+             passage() { echo \"$*\" > args; echo github; echo gitlab; echo other; }
+             COMP_WORDS=(passage --vault team --output json show gi)
+             COMP_CWORD=6
+             _passage_names
+             echo \"${COMPREPLY[*]}\"",
+        )
+        .assert()
+        .success()
+        .stdout("github gitlab\n");
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("args")).unwrap(),
+        "__complete entries --vault team\n"
+    );
+}
+
+#[test]
+fn keyring_lookup_creates_no_files() {
+    let dir = tempdir();
+    let storage = dir.path().join("storage");
+
+    passage()
+        .env("PASSAGE_STORAGE_FOLDER", &storage)
+        .arg("--no-keyring")
+        .arg("init")
+        .write_stdin("p\n")
+        .assert()
+        .success();
+
+    // looking for the passphrase of a storage folder without an identifier neither fails
+    // nor gives it one
+    passage()
+        .env("PASSAGE_STORAGE_FOLDER", &storage)
+        .args(["__complete", "entries"])
+        .assert()
+        .success()
+        .stdout("");
+    passage()
+        .env("PASSAGE_STORAGE_FOLDER", &storage)
+        .args(["keyring", "check"])
+        .assert()
+        .success();
+    assert!(!storage.join(".vault_id").exists());
+
+    // the local folder of remote storage might not exist yet
+    passage()
+        .env("PASSAGE_STORAGE_FOLDER", dir.path().join("missing"))
+        .env("PASSAGE_BACKEND", "s3")
+        .args(["__complete", "entries"])
+        .assert()
+        .success()
+        .stdout("");
+    assert!(!dir.path().join("missing").exists());
+}
+
+#[test]
+fn man_pages() {
+    passage().arg("man").assert().success().stdout(
+        predicate::str::starts_with(".TH PASSAGE 1")
+            .and(predicate::str::contains(".SH HOOKS"))
+            .and(predicate::str::contains("PASSAGE_STORAGE_FOLDER"))
+            .and(predicate::str::contains("\\fB6 (entry_not_found)\\fR")),
+    );
+    passage()
+        .args(["man", "vault", "add"])
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::starts_with(".TH PASSAGE\\-VAULT\\-ADD 1")
+                .and(predicate::str::contains("\\fB<storage\\-folder>\\fR"))
+                .and(predicate::str::contains("\\fBpassage\\-vault\\fR(1)")),
+        );
+    passage().args(["man", "nope"]).assert().code(1);
+
+    let dir = tempdir();
+    passage()
+        .args(["man", "--dir", dir.path().to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("passage-vault-add.1"));
+    assert!(dir.path().join("passage.1").exists());
+    assert!(dir.path().join("passage-git-sync.1").exists());
+    assert!(!dir.path().join("passage-help.1").exists());
+}